@@ -1,66 +1,132 @@
 use serde_json::{Value, json};
-use axum::extract::{Json, TypedHeader};
-use axum::headers::{Header, HeaderName, HeaderValue};
+use axum::extract::{Extension, Json};
+use axum::extract::rejection::JsonRejection;
+use axum::body::Bytes;
+use axum::http::{Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
 use tokio::task;
-use tracing::info;
+use tracing::{field, info, warn};
 use std::time;
+use std::sync::{Arc, Mutex};
 
-#[cfg(not(feature = "spl"))]
-use crate::bitboard::mode::{Standard, StandardWrapped};
-use crate::bitboard::{self, Bitboard, move_gen};
+use crate::bitboard::{Bitboard, Gamemode, Move, move_gen};
+use crate::config;
+use crate::engine::{EngineId, SearchResult};
+use crate::bitboard::mode::{self, Mode, ModeVisitor};
 use crate::minimax;
-use crate::uct;
-use crate::wire_rep::GameState;
+use crate::session::{self, Session};
+use crate::wire_rep::{GameState, RulesetName};
 
-pub struct StartTimeHeader(u64);
+/// When the server received a request, stamped before its body is read, because the game's timeout is already running.
+#[derive(Clone, Copy)]
+pub struct ReceivedAt(pub time::SystemTime);
 
-impl Header for StartTimeHeader {
-    fn name() -> &'static HeaderName {
-        static START_TIME_HEADER: HeaderName = HeaderName::from_static("x-received-at");
-        &START_TIME_HEADER
+/// Middleware that stamps every request with the time it was received.
+pub async fn stamp_receipt<B>(mut request: Request<B>, next: Next<B>) -> Response {
+    request.extensions_mut().insert(ReceivedAt(time::SystemTime::now()));
+    next.run(request).await
+}
+
+/// A request that we can't play, answered with a 400 that lists every problem.
+/// Move requests still get a best-effort move in the same response.
+pub struct RequestError {
+    errors: Vec<String>,
+    fallback_move: Option<Move>,
+}
+
+impl RequestError {
+    fn new(errors: Vec<String>) -> Self {
+        RequestError{ errors, fallback_move: None }
     }
 
-    fn decode<'i, I>(values: &mut I) -> Result<Self, axum::headers::Error>
-    where
-        I: Iterator<Item = &'i HeaderValue>,
-    {
-        let value = values
-            .next()
-            .ok_or_else(axum::headers::Error::invalid)?;
-
-        let x = if let Ok(val) = value.to_str() {
-            val.replace(".", "")
-        } else {
-            return Err(axum::headers::Error::invalid())
-        };
-
-        if let Ok(val) = x.parse::<u64>() {
-            Ok(StartTimeHeader(val))
-        } else {
-            Err(axum::headers::Error::invalid())
+    fn with_move(mut self, mv: Move) -> Self {
+        self.fallback_move = Some(mv);
+        self
+    }
+}
+
+impl IntoResponse for RequestError {
+    fn into_response(self) -> Response {
+        warn!(errors = ?self.errors, fallback_move = ?self.fallback_move, "invalid_request");
+        let mut body = json!({ "errors": self.errors });
+        if let Some(mv) = self.fallback_move {
+            body["move"] = mv.to_json()["move"].clone();
         }
+        (StatusCode::BAD_REQUEST, Json(body)).into_response()
     }
+}
 
-    fn encode<E>(&self, values: &mut E)
-    where
-        E: Extend<HeaderValue>,
-    {
-        let value = HeaderValue::from_str(&self.0.to_string()).unwrap();
-        values.extend(std::iter::once(value));
+/// Unpacks and validates a request body.
+fn validated(payload: Result<Json<GameState>, JsonRejection>) -> Result<GameState, RequestError> {
+    let Json(state) = payload.map_err(|rejection| RequestError::new(vec![rejection.body_text()]))?;
+    state.validate().map_err(|errors| RequestError::new(errors.iter().map(ToString::to_string).collect()))?;
+    Ok(state)
+}
+
+/// Like `validated`, but attaches a move that is safe to play to the error.
+/// Takes the raw body, so that a request that doesn't parse can still be read in part for the move.
+fn validated_move(body: &[u8]) -> Result<GameState, RequestError> {
+    match serde_json::from_slice::<GameState>(body) {
+        Ok(state) => match state.validate() {
+            Ok(()) => Ok(state),
+            Err(errors) => Err(RequestError::new(errors.iter().map(ToString::to_string).collect()).with_move(move_gen::fallback_move(&state))),
+        },
+        Err(err) => {
+            let mv = match serde_json::from_slice::<Value>(body) {
+                Ok(value) => move_gen::fallback_move(&GameState::from_partial_json(&value)),
+                Err(_) => Move::Up,
+            };
+            Err(RequestError::new(vec![format!("failed to parse the gamestate: {}", err)]).with_move(mv))
+        },
     }
 }
 
+fn unsupported(state: &GameState) -> RequestError {
+    RequestError::new(vec![format!(
+        "snake count or board size not supported S: {}, W: {}, H: {}",
+        state.board.snakes.len(), state.board.width, state.board.height,
+    )])
+}
+
 pub async fn handle_index() -> Json<Value> {
+    let appearance = &config::get().appearance;
     Json(json!({
         "apiversion": "1",
-        "author": "JonathanArns",
-        "color": "#900050",
-        "head": "cosmic-horror-special",
-        "tail": "cosmic-horror",
+        "author": appearance.author,
+        "color": appearance.color,
+        "head": appearance.head,
+        "tail": appearance.tail,
     }))
 }
 
-pub async fn handle_start(Json(_req): Json<GameState>) {}
+/// Shows the configuration that the server is running with, secrets are redacted.
+pub async fn handle_config() -> Json<Value> {
+    Json(serde_json::to_value(config::get()).unwrap())
+}
+
+pub async fn handle_start(payload: Result<Json<GameState>, JsonRejection>) -> Result<(), RequestError> {
+    let state = validated(payload)?;
+    // games with an unknown ruleset are played by the standard rules
+    if state.game.ruleset.name == RulesetName::Unknown {
+        warn!(game.id = state.game.id.as_str(), game.map = state.game.map.as_str(), "unknown_ruleset");
+    }
+    // a fresh table is only worth it if no other game is using the current one
+    if session::active_count() == 0 {
+        // a ponder search must not write to the table while it's cleared
+        spawn_blocking_with_tracing(|| {
+            minimax::ponder::stop();
+            minimax::clear_ttable();
+        }).await.unwrap();
+    }
+    session::start(state);
+    Ok(())
+}
+
+pub async fn handle_end(payload: Result<Json<GameState>, JsonRejection>) -> Result<(), RequestError> {
+    end(validated(payload)?).await;
+    Ok(())
+}
 
 #[tracing::instrument(
     name = "handle_end",
@@ -70,7 +136,9 @@ pub async fn handle_start(Json(_req): Json<GameState>) {}
         game.id = state.game.id.as_str()
     )
 )]
-pub async fn handle_end(Json(state): Json<GameState>) {
+async fn end(state: GameState) {
+    session::end(&state.game.id);
+    spawn_blocking_with_tracing(minimax::ponder::stop).await.unwrap();
     for snake in state.board.snakes {
         if snake.health > 0 {
             info!(game.winner.name = snake.name.as_str(), game.winner.id = snake.id.as_str(), game.source = state.game.source.as_str(), game.id = state.game.id.as_str(), "game_winner");
@@ -92,397 +160,220 @@ where
     task::spawn_blocking(move || current_span.in_scope(f))
 }
 
-fn is_wrapped(state: &GameState) -> bool {
-    if let Some(name) = state.game.ruleset["name"].as_str() {
-        name.contains("wrapped")
-    } else {
-        false
-    }
+/// Answers with the engine that the configuration selects for the game.
+pub async fn handle_move(received_at: Option<Extension<ReceivedAt>>, body: Bytes) -> Result<Json<Value>, RequestError> {
+    let state = validated_move(&body)?;
+    let engine = config::get().engines.select(Gamemode::from_gamestate(&state), state.board.snakes.len());
+    search_move(time_received(received_at), state, engine).await
+}
+
+/// Always answers with MCTS, whatever the configuration selects.
+pub async fn handle_move_mcts(received_at: Option<Extension<ReceivedAt>>, body: Bytes) -> Result<Json<Value>, RequestError> {
+    search_move(time_received(received_at), validated_move(&body)?, EngineId::Mcts).await
 }
 
 #[tracing::instrument(
     name = "handle_move",
-    skip(state, start_time_header),
+    skip(state, received_at, engine),
     fields(
         game.source = state.game.source.as_str(),
         game.id = state.game.id.as_str(),
         game.turn = state.turn,
-        search.algo = "mcts"
+        search.engine = ?engine,
+        search.evaluation = field::Empty,
+        search.depth = field::Empty,
+        search.seldepth = field::Empty,
+        search.nodes = field::Empty,
+        search.nodes_per_second = field::Empty,
+        search.tt_hit_rate = field::Empty,
+        search.pv = field::Empty,
+        search.root_moves = field::Empty
     )
 )]
-pub async fn handle_move_mcts(start_time_header: Option<TypedHeader<StartTimeHeader>>, Json(state): Json<GameState>) -> Json<Value> {
-    let deadline = if let Some(TypedHeader(StartTimeHeader(value))) = start_time_header {
-        time::UNIX_EPOCH + time::Duration::from_millis(value) + time::Duration::from_millis(((state.game.timeout / 2).max(state.game.timeout.max(100) - 100)).into())
-    } else {
-        time::SystemTime::now() + time::Duration::from_millis(((state.game.timeout / 2).max(state.game.timeout.max(100) - 100)).into())
-    };
+async fn search_move(received_at: time::SystemTime, mut state: GameState, engine: EngineId) -> Result<Json<Value>, RequestError> {
+    let session = session::update(&mut state);
+    let (deadline, answer_by) = deadlines(received_at, state.game.timeout, &session.lock().unwrap());
+    let ponder_deadline = minimax::ponder::is_enabled(session::active_count())
+        .then(|| deadline + time::Duration::from_millis(state.game.timeout.into()));
+    let mv = watched_search(state.clone(), Search { engine, deadline, session: session.clone() }, answer_by, session.clone()).await?;
+    session.lock().unwrap().set_answer_time(received_at.elapsed().unwrap_or_default());
+    // the watchdog only covers the search, everything else happens while the answer is sent
+    spawn_blocking_with_tracing(move || mode::dispatch(state, AfterSearch { engine, mv, ponder_deadline, session }));
+    Ok(Json(mv.to_json()))
+}
 
-    #[cfg(not(feature = "spl"))]
-    let (mv, _score) = match (state.board.snakes.len(), state.board.width, state.board.height, is_wrapped(&state)) {
-        (1, 11, 11, true) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<1, StandardWrapped>::from_gamestate(state), deadline)).await.unwrap(),
-        (2, 11, 11, true) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<2, StandardWrapped>::from_gamestate(state), deadline)).await.unwrap(),
-        (3, 11, 11, true) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<3, StandardWrapped>::from_gamestate(state), deadline)).await.unwrap(),
-        (4, 11, 11, true) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<4, StandardWrapped>::from_gamestate(state), deadline)).await.unwrap(),
-
-        (1, 11, 11, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<1, Standard>::from_gamestate(state), deadline)).await.unwrap(),
-        (2, 11, 11, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<2, Standard>::from_gamestate(state), deadline)).await.unwrap(),
-        (3, 11, 11, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<3, Standard>::from_gamestate(state), deadline)).await.unwrap(),
-        (4, 11, 11, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<4, Standard>::from_gamestate(state), deadline)).await.unwrap(),
-        _ => panic!("Snake count or board size not supported S: {}, W: {}, H: {}, please enable the 'spl' feature.", state.board.snakes.len(), state.board.width, state.board.height),
-    };
+struct Search {
+    engine: EngineId,
+    deadline: time::SystemTime,
+    session: Arc<Mutex<Session>>,
+}
 
-    #[cfg(feature = "spl")]
-    let (mv, _score) = match (state.board.snakes.len(), state.board.width, state.board.height, is_wrapped(&state), is_hazard_stacking(&state)) {
-        (1, 7, 7, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<1, 7, 7, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (2, 7, 7, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<2, 7, 7, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (3, 7, 7, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<3, 7, 7, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (4, 7, 7, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<4, 7, 7, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (5, 7, 7, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<5, 7, 7, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (6, 7, 7, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<6, 7, 7, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (7, 7, 7, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<7, 7, 7, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (8, 7, 7, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<8, 7, 7, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-
-        (1, 7, 7, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<1, 7, 7, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (2, 7, 7, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<2, 7, 7, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (3, 7, 7, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<3, 7, 7, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (4, 7, 7, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<4, 7, 7, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (5, 7, 7, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<5, 7, 7, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (6, 7, 7, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<6, 7, 7, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (7, 7, 7, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<7, 7, 7, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (8, 7, 7, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<8, 7, 7, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-
-        (1, 11, 11, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<1, 11, 11, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (2, 11, 11, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<2, 11, 11, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (3, 11, 11, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<3, 11, 11, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (4, 11, 11, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<4, 11, 11, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (5, 11, 11, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<5, 11, 11, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (6, 11, 11, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<6, 11, 11, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (7, 11, 11, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<7, 11, 11, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (8, 11, 11, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<8, 11, 11, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-
-        (1, 11, 11, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<1, 11, 11, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (2, 11, 11, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<2, 11, 11, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (3, 11, 11, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<3, 11, 11, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (4, 11, 11, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<4, 11, 11, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (5, 11, 11, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<5, 11, 11, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (6, 11, 11, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<6, 11, 11, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (7, 11, 11, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<7, 11, 11, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (8, 11, 11, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<8, 11, 11, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-
-        (1, 19, 19, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<1, 19, 19, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (2, 19, 19, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<2, 19, 19, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (3, 19, 19, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<3, 19, 19, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (4, 19, 19, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<4, 19, 19, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (5, 19, 19, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<5, 19, 19, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (6, 19, 19, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<6, 19, 19, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (7, 19, 19, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<7, 19, 19, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (8, 19, 19, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<8, 19, 19, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (9, 19, 19, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<9, 19, 19, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (10, 19, 19, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<10, 19, 19, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (11, 19, 19, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<11, 19, 19, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (12, 19, 19, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<12, 19, 19, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (13, 19, 19, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<13, 19, 19, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (14, 19, 19, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<14, 19, 19, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (15, 19, 19, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<15, 19, 19, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (16, 19, 19, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<16, 19, 19, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-
-        (1, 19, 19, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<1, 19, 19, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (2, 19, 19, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<2, 19, 19, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (3, 19, 19, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<3, 19, 19, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (4, 19, 19, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<4, 19, 19, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (5, 19, 19, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<5, 19, 19, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (6, 19, 19, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<6, 19, 19, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (7, 19, 19, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<7, 19, 19, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (8, 19, 19, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<8, 19, 19, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (9, 19, 19, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<9, 19, 19, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (10, 19, 19, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<10, 19, 19, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (11, 19, 19, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<11, 19, 19, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (12, 19, 19, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<12, 19, 19, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (13, 19, 19, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<13, 19, 19, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (14, 19, 19, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<14, 19, 19, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (15, 19, 19, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<15, 19, 19, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (16, 19, 19, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<16, 19, 19, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-
-        // maze_arcade
-        (1, 19, 21, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<1, 19, 21, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (2, 19, 21, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<2, 19, 21, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (3, 19, 21, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<3, 19, 21, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (4, 19, 21, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<4, 19, 21, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-
-        (1, 25, 25, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<1, 25, 25, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (2, 25, 25, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<2, 25, 25, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (3, 25, 25, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<3, 25, 25, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (4, 25, 25, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<4, 25, 25, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (5, 25, 25, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<5, 25, 25, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (6, 25, 25, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<6, 25, 25, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (7, 25, 25, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<7, 25, 25, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (8, 25, 25, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<8, 25, 25, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (9, 25, 25, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<9, 25, 25, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (10, 25, 25, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<10, 25, 25, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (11, 25, 25, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<11, 25, 25, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (12, 25, 25, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<12, 25, 25, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (13, 25, 25, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<13, 25, 25, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (14, 25, 25, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<14, 25, 25, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (15, 25, 25, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<15, 25, 25, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (16, 25, 25, true, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<16, 25, 25, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-
-        (1, 25, 25, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<1, 25, 25, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (2, 25, 25, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<2, 25, 25, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (3, 25, 25, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<3, 25, 25, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (4, 25, 25, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<4, 25, 25, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (5, 25, 25, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<5, 25, 25, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (6, 25, 25, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<6, 25, 25, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (7, 25, 25, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<7, 25, 25, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (8, 25, 25, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<8, 25, 25, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (9, 25, 25, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<9, 25, 25, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (10, 25, 25, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<10, 25, 25, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (11, 25, 25, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<11, 25, 25, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (12, 25, 25, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<12, 25, 25, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (13, 25, 25, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<13, 25, 25, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (14, 25, 25, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<14, 25, 25, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (15, 25, 25, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<15, 25, 25, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (16, 25, 25, false, false) => spawn_blocking_with_tracing(move || uct::search(&bitboard::Bitboard::<16, 25, 25, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        _ => panic!("Snake count or board size not supported S: {}, W: {}, H: {}", state.board.snakes.len(), state.board.width, state.board.height),
-    };
-    Json(mv.to_json())
+impl ModeVisitor for Search {
+    type Output = Move;
+
+    fn visit<const S: usize, MODE: Mode>(self, state: GameState) -> Self::Output {
+        minimax::ponder::stop();
+        let board = take_board::<S, MODE>(&self.session, state);
+        let result = self.engine.search(&board, self.deadline);
+        record_search_result(&result);
+        self.session.lock().unwrap().set_board(board);
+        result.best_move
+    }
 }
 
-#[tracing::instrument(
-    name = "handle_move",
-    skip(state, start_time_header),
-    fields(
-        game.source = state.game.source.as_str(),
-        game.id = state.game.id.as_str(),
-        game.turn = state.turn,
-        search.algo = "minimax"
-    )
-)]
-pub async fn handle_move_minimax(start_time_header: Option<TypedHeader<StartTimeHeader>>, Json(state): Json<GameState>) -> Json<Value> {
-    let deadline = if let Some(TypedHeader(StartTimeHeader(value))) = start_time_header {
-        // we are playing behind a proxy with "accurate" timing information
-        time::UNIX_EPOCH + time::Duration::from_millis(value) + time::Duration::from_millis(((state.game.timeout / 2).max(state.game.timeout.max(60) - 60)).into())
-    } else {
-        time::SystemTime::now() + time::Duration::from_millis(((state.game.timeout / 2).max(state.game.timeout.max(100) - 100)).into())
-    };
+/// Ponders on the move that we answered with.
+struct AfterSearch {
+    engine: EngineId,
+    mv: Move,
+    /// Keep working on the expected next position until this deadline after replying.
+    ponder_deadline: Option<time::SystemTime>,
+    session: Arc<Mutex<Session>>,
+}
 
-    #[cfg(not(feature = "spl"))]
-    let (mv, _score, _depth) = match (state.board.snakes.len(), state.board.width, state.board.height, is_wrapped(&state)) {
-        (1, 11, 11, true) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<1, StandardWrapped>::from_gamestate(state), deadline)).await.unwrap(),
-        (2, 11, 11, true) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<2, StandardWrapped>::from_gamestate(state), deadline)).await.unwrap(),
-        (3, 11, 11, true) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<3, StandardWrapped>::from_gamestate(state), deadline)).await.unwrap(),
-        (4, 11, 11, true) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<4, StandardWrapped>::from_gamestate(state), deadline)).await.unwrap(),
-
-        (1, 11, 11, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<1, Standard>::from_gamestate(state), deadline)).await.unwrap(),
-        (2, 11, 11, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<2, Standard>::from_gamestate(state), deadline)).await.unwrap(),
-        (3, 11, 11, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<3, Standard>::from_gamestate(state), deadline)).await.unwrap(),
-        (4, 11, 11, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<4, Standard>::from_gamestate(state), deadline)).await.unwrap(),
-        _ => panic!("Snake count or board size not supported S: {}, W: {}, H: {}, WRAP: {:?}", state.board.snakes.len(), state.board.width, state.board.height, is_wrapped(&state)),
-    };
+impl ModeVisitor for AfterSearch {
+    type Output = ();
 
-    #[cfg(feature = "spl")]
-    let (mv, _score, _depth) = match (state.board.snakes.len(), state.board.width, state.board.height, is_wrapped(&state), is_hazard_stacking(&state)) {
-        (1, 7, 7, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<1, 7, 7, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (2, 7, 7, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<2, 7, 7, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (3, 7, 7, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<3, 7, 7, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (4, 7, 7, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<4, 7, 7, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (5, 7, 7, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<5, 7, 7, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (6, 7, 7, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<6, 7, 7, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (7, 7, 7, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<7, 7, 7, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (8, 7, 7, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<8, 7, 7, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-
-        (1, 7, 7, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<1, 7, 7, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (2, 7, 7, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<2, 7, 7, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (3, 7, 7, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<3, 7, 7, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (4, 7, 7, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<4, 7, 7, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (5, 7, 7, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<5, 7, 7, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (6, 7, 7, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<6, 7, 7, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (7, 7, 7, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<7, 7, 7, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (8, 7, 7, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<8, 7, 7, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-
-        // stacking hazards
-        (1, 11, 11, true, true) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<1, 11, 11, true, true, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (2, 11, 11, true, true) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<2, 11, 11, true, true, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (3, 11, 11, true, true) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<3, 11, 11, true, true, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (4, 11, 11, true, true) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<4, 11, 11, true, true, 0>::from_gamestate(state), deadline)).await.unwrap(),
-
-        (1, 11, 11, false, true) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<1, 11, 11, false, true, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (2, 11, 11, false, true) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<2, 11, 11, false, true, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (3, 11, 11, false, true) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<3, 11, 11, false, true, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (4, 11, 11, false, true) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<4, 11, 11, false, true, 0>::from_gamestate(state), deadline)).await.unwrap(),
-
-        (1, 11, 11, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<1, 11, 11, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (2, 11, 11, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<2, 11, 11, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (3, 11, 11, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<3, 11, 11, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (4, 11, 11, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<4, 11, 11, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (5, 11, 11, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<5, 11, 11, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (6, 11, 11, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<6, 11, 11, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (7, 11, 11, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<7, 11, 11, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (8, 11, 11, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<8, 11, 11, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-
-        (1, 11, 11, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<1, 11, 11, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (2, 11, 11, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<2, 11, 11, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (3, 11, 11, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<3, 11, 11, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (4, 11, 11, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<4, 11, 11, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (5, 11, 11, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<5, 11, 11, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (6, 11, 11, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<6, 11, 11, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (7, 11, 11, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<7, 11, 11, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (8, 11, 11, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<8, 11, 11, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-
-        (1, 19, 19, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<1, 19, 19, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (2, 19, 19, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<2, 19, 19, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (3, 19, 19, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<3, 19, 19, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (4, 19, 19, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<4, 19, 19, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (5, 19, 19, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<5, 19, 19, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (6, 19, 19, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<6, 19, 19, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (7, 19, 19, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<7, 19, 19, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (8, 19, 19, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<8, 19, 19, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (9, 19, 19, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<9, 19, 19, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (10, 19, 19, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<10, 19, 19, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (11, 19, 19, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<11, 19, 19, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (12, 19, 19, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<12, 19, 19, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (13, 19, 19, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<13, 19, 19, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (14, 19, 19, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<14, 19, 19, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (15, 19, 19, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<15, 19, 19, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (16, 19, 19, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<16, 19, 19, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-
-        (1, 19, 19, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<1, 19, 19, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (2, 19, 19, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<2, 19, 19, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (3, 19, 19, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<3, 19, 19, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (4, 19, 19, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<4, 19, 19, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (5, 19, 19, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<5, 19, 19, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (6, 19, 19, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<6, 19, 19, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (7, 19, 19, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<7, 19, 19, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (8, 19, 19, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<8, 19, 19, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (9, 19, 19, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<9, 19, 19, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (10, 19, 19, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<10, 19, 19, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (11, 19, 19, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<11, 19, 19, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (12, 19, 19, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<12, 19, 19, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (13, 19, 19, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<13, 19, 19, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (14, 19, 19, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<14, 19, 19, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (15, 19, 19, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<15, 19, 19, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (16, 19, 19, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<16, 19, 19, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-
-        // maze_arcade
-        (1, 19, 21, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<1, 19, 21, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (2, 19, 21, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<2, 19, 21, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (3, 19, 21, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<3, 19, 21, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (4, 19, 21, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<4, 19, 21, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-
-        (1, 25, 25, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<1, 25, 25, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (2, 25, 25, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<2, 25, 25, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (3, 25, 25, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<3, 25, 25, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (4, 25, 25, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<4, 25, 25, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (5, 25, 25, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<5, 25, 25, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (6, 25, 25, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<6, 25, 25, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (7, 25, 25, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<7, 25, 25, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (8, 25, 25, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<8, 25, 25, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (9, 25, 25, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<9, 25, 25, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (10, 25, 25, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<10, 25, 25, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (11, 25, 25, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<11, 25, 25, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (12, 25, 25, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<12, 25, 25, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (13, 25, 25, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<13, 25, 25, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (14, 25, 25, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<14, 25, 25, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (15, 25, 25, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<15, 25, 25, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (16, 25, 25, true, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<16, 25, 25, true, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-
-        (1, 25, 25, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<1, 25, 25, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (2, 25, 25, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<2, 25, 25, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (3, 25, 25, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<3, 25, 25, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (4, 25, 25, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<4, 25, 25, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (5, 25, 25, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<5, 25, 25, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (6, 25, 25, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<6, 25, 25, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (7, 25, 25, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<7, 25, 25, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (8, 25, 25, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<8, 25, 25, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (9, 25, 25, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<9, 25, 25, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (10, 25, 25, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<10, 25, 25, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (11, 25, 25, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<11, 25, 25, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (12, 25, 25, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<12, 25, 25, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (13, 25, 25, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<13, 25, 25, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (14, 25, 25, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<14, 25, 25, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (15, 25, 25, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<15, 25, 25, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        (16, 25, 25, false, false) => spawn_blocking_with_tracing(move || minimax::search(&bitboard::Bitboard::<16, 25, 25, false, false, 0>::from_gamestate(state), deadline)).await.unwrap(),
-        _ => panic!("Snake count or board size not supported S: {}, W: {}, H: {}, WRAP: {:?}, HZSTACK: {:?}", state.board.snakes.len(), state.board.width, state.board.height, is_wrapped(&state), is_hazard_stacking(&state)),
-    };
-    Json(mv.to_json())
+    fn visit<const S: usize, MODE: Mode>(self, state: GameState) -> Self::Output {
+        if let Some(ponder_deadline) = self.ponder_deadline {
+            let board = take_board::<S, MODE>(&self.session, state);
+            self.engine.ponder(&board, self.mv, ponder_deadline);
+        }
+    }
+}
+
+/// Takes the board that an earlier stage of the move request left in the session,
+/// or builds it if there is none, for example because the search was cut short.
+fn take_board<const S: usize, MODE: Mode>(session: &Mutex<Session>, state: GameState) -> Bitboard<S, MODE> {
+    let board = session.lock().unwrap().take_board(state.turn);
+    board.unwrap_or_else(|| Bitboard::from_gamestate(state))
+}
+
+/// Attaches a search result to the move request's span.
+fn record_search_result<const S: usize>(result: &SearchResult<S>) {
+    let span = tracing::Span::current();
+    span.record("search.evaluation", field::debug(&result.evaluation));
+    span.record("search.depth", result.depth);
+    span.record("search.seldepth", result.seldepth);
+    span.record("search.nodes", result.nodes);
+    span.record("search.nodes_per_second", result.nodes_per_second);
+    span.record("search.tt_hit_rate", result.tt_hit_rate);
+    span.record("search.pv", field::debug(&result.pv));
+    span.record("search.root_moves", field::debug(&result.root_moves));
 }
 
+
 /// Use the type parameter TT to manually override the tt_id of the created Bitboard in training mode.
 /// This is used in training, since the tt_id is also used to choose eval weights there.
 #[cfg(feature = "training")]
-pub async fn training_handle_move_minimax<const TT: u8>(Json(mut state): Json<GameState>, start_time_header: Option<TypedHeader<StartTimeHeader>>) -> Json<Value> {
+pub async fn training_handle_move_minimax<const TT: u8>(received_at: Option<Extension<ReceivedAt>>, body: Bytes) -> Result<Json<Value>, RequestError> {
+    let mut state = validated_move(&body)?;
     state.game.id = "".to_string();
     state.you.id = TT.to_string();
-    handle_move_minimax(Json(state), start_time_header).await
+    search_move(time_received(received_at), state, EngineId::Minimax).await
 }
 
-pub async fn simulate_turn(Json(state): Json<GameState>) -> Json<GameState> {
-    match (state.board.snakes.len(), state.board.width, state.board.height, is_wrapped(&state)) {
-        (1, 11, 11, true) => {
-            let moves = move_gen::moves_from_gamestate::<1>(&state);
-            let mut board = Bitboard::<1, StandardWrapped>::from_gamestate(state);
-            println!("{:?}", board);
-            (board.apply_moves.clone())(&mut board, &moves);
-            println!("{:?}", board);
-            Json(board.to_gamestate())
-        },
-        (2, 11, 11, true) => {
-            let moves = move_gen::moves_from_gamestate::<2>(&state);
-            let mut board = Bitboard::<2, StandardWrapped>::from_gamestate(state);
-            println!("{:?}", board);
-            (board.apply_moves.clone())(&mut board, &moves);
-            println!("{:?}", board);
-            Json(board.to_gamestate())
-        },
-        (3, 11, 11, true) => {
-            let moves = move_gen::moves_from_gamestate::<3>(&state);
-            let mut board = Bitboard::<3, StandardWrapped>::from_gamestate(state);
-            println!("{:?}", board);
-            (board.apply_moves.clone())(&mut board, &moves);
-            println!("{:?}", board);
-            Json(board.to_gamestate())
-        },
-        (4, 11, 11, true) => {
-            let moves = move_gen::moves_from_gamestate::<4>(&state);
-            let mut board = Bitboard::<4, StandardWrapped>::from_gamestate(state);
-            println!("{:?}", board);
-            (board.apply_moves.clone())(&mut board, &moves);
-            println!("{:?}", board);
-            Json(board.to_gamestate())
-        },
+pub async fn simulate_turn(payload: Result<Json<GameState>, JsonRejection>) -> Result<Json<GameState>, RequestError> {
+    Ok(Json(dispatch_blocking(validated(payload)?, SimulateTurn).await?))
+}
 
-        (1, 11, 11, false) => {
-            let moves = move_gen::moves_from_gamestate::<1>(&state);
-            let mut board = Bitboard::<1, Standard>::from_gamestate(state);
-            println!("{:?}", board);
-            (board.apply_moves.clone())(&mut board, &moves);
-            println!("{:?}", board);
-            Json(board.to_gamestate())
-        },
-        (2, 11, 11, false) => {
-            let moves = move_gen::moves_from_gamestate::<2>(&state);
-            let mut board = Bitboard::<2, Standard>::from_gamestate(state);
-            println!("{:?}", board);
-            (board.apply_moves.clone())(&mut board, &moves);
-            println!("{:?}", board);
-            Json(board.to_gamestate())
-        },
-        (3, 11, 11, false) => {
-            let moves = move_gen::moves_from_gamestate::<3>(&state);
-            let mut board = Bitboard::<3, Standard>::from_gamestate(state);
-            println!("{:?}", board);
-            (board.apply_moves.clone())(&mut board, &moves);
-            println!("{:?}", board);
-            Json(board.to_gamestate())
-        },
-        (4, 11, 11, false) => {
-            let moves = move_gen::moves_from_gamestate::<4>(&state);
-            let mut board = Bitboard::<4, Standard>::from_gamestate(state);
-            println!("{:?}", board);
-            (board.apply_moves.clone())(&mut board, &moves);
-            println!("{:?}", board);
-            Json(board.to_gamestate())
-        },
+struct SimulateTurn;
+
+impl ModeVisitor for SimulateTurn {
+    type Output = GameState;
+
+    fn visit<const S: usize, MODE: Mode>(self, state: GameState) -> Self::Output {
+        let moves = move_gen::moves_from_gamestate::<S>(&state);
+        let mut board = Bitboard::<S, MODE>::from_gamestate(state);
+        board.play_moves(&moves);
+        board.to_gamestate()
+    }
+}
+
+/// Returns when the request was received, or now if it wasn't stamped by the middleware.
+fn time_received(received_at: Option<Extension<ReceivedAt>>) -> time::SystemTime {
+    match received_at {
+        Some(Extension(ReceivedAt(time))) => time,
+        None => time::SystemTime::now(),
+    }
+}
+
+/// Returns the search deadline and the watchdog deadline of a move request.
+/// Both leave room for the game's network latency, which is capped at half of the timeout.
+fn deadlines(received_at: time::SystemTime, timeout: u32, session: &Session) -> (time::SystemTime, time::SystemTime) {
+    let config = &config::get().timing;
+    let ms = |x: u32| time::Duration::from_millis(x.into());
+    let latency = session.network_latency()
+        .unwrap_or(ms(config.default_latency_ms))
+        .min(ms(timeout) / 2);
+    let time_left = ms(timeout) - latency;
+    (
+        received_at + time_left.saturating_sub(ms(config.search_margin_ms)),
+        received_at + time_left.saturating_sub(ms(config.watchdog_margin_ms)),
+    )
+}
 
-        _ => panic!("Snake count or board size not supported S: {}, W: {}, H: {}, WRAP: {:?}", state.board.snakes.len(), state.board.width, state.board.height, is_wrapped(&state)),
+/// Plays the first move that allowed_moves comes up with, which is cheap enough to always be ready.
+/// Leaves the board in the session for the search.
+struct SafeMove {
+    session: Arc<Mutex<Session>>,
+}
+
+impl ModeVisitor for SafeMove {
+    type Output = Move;
+
+    fn visit<const S: usize, MODE: Mode>(self, state: GameState) -> Self::Output {
+        let board = Bitboard::<S, MODE>::from_gamestate(state);
+        let mv = move_gen::allowed_moves(&board, 0)[0];
+        self.session.lock().unwrap().set_board(board);
+        mv
     }
 }
+
+/// Runs a search on a blocking thread, but always answers by the watchdog deadline.
+/// If the search panics or runs late, the best move it found so far is played, or a safe move if it hasn't found one yet.
+async fn watched_search<V>(state: GameState, visitor: V, answer_by: time::SystemTime, session: Arc<Mutex<Session>>) -> Result<Move, RequestError>
+where
+    V: ModeVisitor<Output = Move> + Send + 'static,
+{
+    let safe_move = match mode::dispatch(state.clone(), SafeMove { session }) {
+        Some(mv) => mv,
+        None => return Err(unsupported(&state).with_move(move_gen::fallback_move(&state))),
+    };
+    let watch = Arc::new(minimax::watch::Watch::default());
+    let handle = {
+        let watch = watch.clone();
+        spawn_blocking_with_tracing(move || {
+            let _guard = minimax::watch::watch(watch);
+            mode::dispatch(state, visitor)
+        })
+    };
+    let time_left = answer_by.duration_since(time::SystemTime::now()).unwrap_or_default();
+    let reason = match tokio::time::timeout(time_left, handle).await {
+        Ok(Ok(Some(mv))) => return Ok(mv),
+        Ok(Ok(None)) => unreachable!("the safe move was found for the same gamestate"),
+        Ok(Err(_)) => "panic",
+        Err(_) => {
+            watch.stop();
+            "deadline"
+        },
+    };
+    let (mv, source) = match watch.best_move() {
+        Some(mv) => (mv, "best_so_far"),
+        None => (safe_move, "allowed_moves"),
+    };
+    warn!(watchdog.reason = reason, watchdog.source = source, watchdog.move = ?mv, "watchdog_fallback");
+    Ok(mv)
+}
+
+/// Runs the visitor with the bitboard type that fits the gamestate on a blocking thread.
+async fn dispatch_blocking<V>(state: GameState, visitor: V) -> Result<V::Output, RequestError>
+where
+    V: ModeVisitor + Send + 'static,
+    V::Output: Send + 'static,
+{
+    let error = unsupported(&state);
+    spawn_blocking_with_tracing(move || mode::dispatch(state, visitor))
+        .await
+        .unwrap()
+        .ok_or(error)
+}
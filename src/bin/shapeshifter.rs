@@ -1,4 +1,4 @@
-use axum::{Router, routing::get, routing::post};
+use axum::{Router, middleware, routing::get, routing::post};
 use tower_http::trace::TraceLayer;
 use tracing;
 use log_panics;
@@ -7,14 +7,22 @@ use tracing_subscriber::{Registry, layer::SubscriberExt};
 use opentelemetry::KeyValue;
 use opentelemetry_otlp::WithExportConfig;
 use tonic::metadata::MetadataMap;
-use std::env;
+use std::process;
 
-use shapeshifter::api;
+use shapeshifter::{api, config};
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 1)]
 async fn main() {
+    let config = config::init().unwrap_or_else(|errors| {
+        eprintln!("invalid configuration:");
+        for error in errors {
+            eprintln!("  {}", error);
+        }
+        process::exit(1)
+    });
+
     // set up tracing subscriber
-    if let Ok(key) = env::var("HONEYCOMB_KEY") {
+    if let Some(key) = &config.telemetry.honeycomb_key {
         let mut map = MetadataMap::new();
         map.insert("x-honeycomb-team", key.parse().unwrap());
 
@@ -23,7 +31,7 @@ async fn main() {
             .with_exporter(opentelemetry_otlp::new_exporter()
                 .tonic()
                 .with_protocol(opentelemetry_otlp::Protocol::Grpc)
-                .with_endpoint("https://api.honeycomb.io")
+                .with_endpoint(config.telemetry.honeycomb_endpoint.as_str())
                 .with_metadata(map)
             )
             .with_trace_config(
@@ -59,24 +67,21 @@ async fn main() {
         .route("/", get(api::handle_index))
         .route("/start", post(api::handle_start))
         .route("/end", post(api::handle_end))
-        .route("/move", post(api::handle_move_minimax))
+        .route("/move", post(api::handle_move))
 
         .route("/mcts/", get(api::handle_index))
         .route("/mcts/start", post(api::handle_start))
         .route("/mcts/end", post(api::handle_end))
         .route("/mcts/move", post(api::handle_move_mcts))
 
+        .route("/config", get(api::handle_config))
         .route("/debug/simulate_turn", post(api::simulate_turn))
 
-        .layer(TraceLayer::new_for_http());
-
-    let env_port = env::var("PORT").ok();
-    let env_port = env_port
-        .as_ref()
-        .map(String::as_str)
-        .unwrap_or("8080");
+        .layer(TraceLayer::new_for_http())
+        // outermost, so that the timestamp is taken as early as possible
+        .layer(middleware::from_fn(api::stamp_receipt));
 
-    axum::Server::bind(&("0.0.0.0:".to_owned() + env_port).parse().unwrap())
+    axum::Server::bind(&([0, 0, 0, 0], config.server.port).into())
         .serve(router.into_make_service())
         .await
         .unwrap();
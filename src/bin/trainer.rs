@@ -1,8 +1,7 @@
 #![feature(test, generic_const_exprs, async_closure, let_chains)]
 
-use axum::{Router, routing::get, routing::post};
+use axum::{Router, middleware, routing::get, routing::post};
 use tokio::task;
-use std::env;
 use rand::Rng;
 use rand::distributions::Distribution;
 use rand::seq::SliceRandom;
@@ -35,13 +34,14 @@ async fn main() {
         .route("/3/", get(api::handle_index))
         .route("/3/start", post(api::handle_start))
         .route("/3/end", post(api::handle_end))
-        .route("/3/move", post(api::training_handle_move_minimax::<3>));
+        .route("/3/move", post(api::training_handle_move_minimax::<3>))
 
-    let env_port = env::var("PORT").ok();
-    let addr = "0.0.0.0:".to_owned() + env_port.as_ref().map(String::as_str).unwrap_or("8080");
+        .layer(middleware::from_fn(api::stamp_receipt));
+
+    let addr = std::net::SocketAddr::from(([0, 0, 0, 0], shapeshifter::config::get().server.port));
 
     task::spawn(async move {
-        axum::Server::bind(&addr.parse().unwrap())
+        axum::Server::bind(&addr)
             .serve(router.into_make_service())
             .await
             .unwrap();
@@ -32,6 +32,21 @@ where [(); L]: Sized {
     Bitset::<N, L>::from_array(arr)
 }
 
+/// Computes FULL_BOARD_MASK for boards that don't fill the whole bitset
+pub const fn full_board_mask<const N: usize, const L: usize>(w: usize, h: usize) -> Bitset<N, L>
+where [(); L]: Sized {
+    let mut arr = [0_u64; L];
+    let mut i = 0;
+    loop {
+        if i == w*h {
+            break
+        }
+        arr[i>>6] |= 1_u64<<(i % 64);
+        i += 1;
+    }
+    Bitset::<N, L>::from_array(arr)
+}
+
 /// Computes ALL_BUT_LEFT_EDGE_MASK and ALL_BUT_RIGHT_EDGE_MASK
 pub const fn checker_board_mask<const N: usize, const L: usize>(w: usize, h: usize) -> Bitset<N, L>
 where [(); L]: Sized {
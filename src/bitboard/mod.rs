@@ -1,14 +1,14 @@
 use std::hash::{Hash, Hasher};
 use std::collections::HashMap;
 use std::rc::Rc;
-use std::sync::Arc;
 use colored::{Colorize, Color};
+use serde::{Deserialize, Serialize};
 use serde_json;
 use std::fs::File;
 use std::io::prelude::*;
 
 use crate::minimax;
-use crate::wire_rep;
+use crate::wire_rep::{self, RulesetName};
 
 mod constants;
 mod rules;
@@ -16,16 +16,24 @@ mod bitset;
 pub mod mode;
 pub mod moves;
 pub mod move_gen;
+pub mod zobrist;
 
 pub use mode::Mode;
 pub use moves::Move;
 pub use bitset::{Bitset, BitsetTrait};
+pub use rules::{Rules, SNAIL_TRAIL_LENGTH};
 
-#[derive(PartialEq, Eq, Clone, Copy, Hash, Debug)]
+#[derive(PartialEq, Eq, Clone, Copy, Hash, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Gamemode {
     Standard,
     StandardWithHazard,
     StandardSnailMode,
+    StandardHealingPools,
+    StandardRiversAndBridges,
+    StandardColumns,
+    StandardRings,
+    StandardScatterFill,
 
     Wrapped,
     WrappedWithHazard,
@@ -35,14 +43,20 @@ pub enum Gamemode {
     WrappedIslandsBridges,
 
     Constrictor,
+    WrappedConstrictor,
+    Squad,
+    Solo,
 }
 
 impl Gamemode {
     /// Returns the appropriate gamemode for a gamestate.
-    fn from_gamestate(state: &wire_rep::GameState) -> Self {
-        match state.game.ruleset["name"].as_str() {
-            Some("constrictor") | Some("wrapped-constrictor") => Self::Constrictor,
-            Some("wrapped") => match state.game.map.as_str() {
+    pub fn from_gamestate(state: &wire_rep::GameState) -> Self {
+        match state.game.ruleset.name {
+            RulesetName::Constrictor => Self::Constrictor,
+            RulesetName::WrappedConstrictor => Self::WrappedConstrictor,
+            RulesetName::Squad => Self::Squad,
+            RulesetName::Solo => Self::Solo,
+            RulesetName::Wrapped => match state.game.map.as_str() {
                 "arcade_maze" => Self::WrappedArcadeMaze,
                 "hz_spiral" => Self::WrappedSpiral,
                 "hz_islands_bridges" => Self::WrappedIslandsBridges,
@@ -52,6 +66,14 @@ impl Gamemode {
             },
             _ => match state.game.map.as_str() {
                 "snail_mode" => Self::StandardSnailMode,
+                "healing_pools" => Self::StandardHealingPools,
+                "hz_rivers_bridges"
+                | "rivers_and_bridges"
+                | "rivers_and_bridges_medium"
+                | "rivers_and_bridges_large" => Self::StandardRiversAndBridges,
+                "hz_columns" => Self::StandardColumns,
+                "hz_rings" => Self::StandardRings,
+                "hz_scatter" => Self::StandardScatterFill,
                 _ if state.board.hazards.len() == 0 => Self::Standard,
                 _ => Self::StandardWithHazard,
             },
@@ -64,11 +86,19 @@ impl Gamemode {
             Gamemode::Standard => "standard".to_string(),
             Gamemode::Wrapped => "wrapped".to_string(),
             Gamemode::Constrictor => "constrictor".to_string(),
+            Gamemode::WrappedConstrictor => "wrapped-constrictor".to_string(),
+            Gamemode::Squad => "squad".to_string(),
+            Gamemode::Solo => "solo".to_string(),
             Gamemode::WrappedSpiral => "wrapped-spiral".to_string(),
             Gamemode::WrappedSinkholes => "wrapped-sinkholes".to_string(),
             Gamemode::WrappedWithHazard => "wrapped-with-hazard".to_string(),
             Gamemode::StandardWithHazard => "standard-with-hazard".to_string(),
             Gamemode::StandardSnailMode => "standard-snail-mode".to_string(),
+            Gamemode::StandardHealingPools => "standard-healing-pools".to_string(),
+            Gamemode::StandardRiversAndBridges => "standard-rivers-and-bridges".to_string(),
+            Gamemode::StandardColumns => "standard-columns".to_string(),
+            Gamemode::StandardRings => "standard-rings".to_string(),
+            Gamemode::StandardScatterFill => "standard-scatter-fill".to_string(),
             Gamemode::WrappedArcadeMaze => "wrapped-arcade-maze".to_string(),
             Gamemode::WrappedIslandsBridges => "wrapped-islands-bridges".to_string(),
         }
@@ -77,21 +107,41 @@ impl Gamemode {
     /// Returns the battlesnake map name associated with this gamemode.
     pub fn get_map_name(&self) -> String {
         match *self {
-            Gamemode::Standard | Gamemode::Wrapped | Gamemode::Constrictor => "standard".to_string(),
+            Gamemode::Standard
+            | Gamemode::Wrapped
+            | Gamemode::Constrictor
+            | Gamemode::WrappedConstrictor
+            | Gamemode::Squad
+            | Gamemode::Solo => "standard".to_string(),
             Gamemode::WrappedSpiral => "hz_spiral".to_string(),
-            Gamemode::WrappedSinkholes => "hz_spiral".to_string(),
+            Gamemode::WrappedSinkholes => "sinkholes".to_string(),
             Gamemode::WrappedWithHazard | Gamemode::StandardWithHazard => "royale".to_string(),
             Gamemode::WrappedArcadeMaze => "arcade_maze".to_string(),
             Gamemode::WrappedIslandsBridges => "hz_islands_bridges".to_string(),
             Gamemode::StandardSnailMode => "snail_mode".to_string(),
+            Gamemode::StandardHealingPools => "healing_pools".to_string(),
+            Gamemode::StandardRiversAndBridges => "rivers_and_bridges".to_string(),
+            Gamemode::StandardColumns => "hz_columns".to_string(),
+            Gamemode::StandardRings => "hz_rings".to_string(),
+            Gamemode::StandardScatterFill => "hz_scatter".to_string(),
         }
     }
 
     /// Returns the ruleset name associated with this gamemode.
     pub fn get_ruleset_name(&self) -> String {
         match *self {
-            Gamemode::Standard | Gamemode::StandardWithHazard | Gamemode::StandardSnailMode => "standard".to_string(),
+            Gamemode::Standard
+            | Gamemode::StandardWithHazard
+            | Gamemode::StandardSnailMode
+            | Gamemode::StandardHealingPools
+            | Gamemode::StandardRiversAndBridges
+            | Gamemode::StandardColumns
+            | Gamemode::StandardRings
+            | Gamemode::StandardScatterFill => "standard".to_string(),
             Gamemode::Constrictor => "constrictor".to_string(),
+            Gamemode::WrappedConstrictor => "wrapped-constrictor".to_string(),
+            Gamemode::Squad => "squad".to_string(),
+            Gamemode::Solo => "solo".to_string(),
             Gamemode::Wrapped 
             | Gamemode::WrappedSpiral 
             | Gamemode::WrappedSinkholes 
@@ -102,6 +152,10 @@ impl Gamemode {
     }
 }
 
+/// Returns true if the gamestate is played on a board with wrapped edges.
+pub fn is_wrapped(state: &wire_rep::GameState) -> bool {
+    state.game.ruleset.name.is_wrapped()
+}
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct Snake {
@@ -124,46 +178,177 @@ impl Snake {
     }
 }
 
-#[derive(Clone)]
+/// Hazards can stack on a cell up to this many times, deeper stacks are capped.
+pub const MAX_HAZARD_STACK: usize = 8;
+
+/// A cell that a snake shares with the body of an ally, which squads can allow.
+/// The cell's direction bits belong to the snake that got there first, each crossing holds the
+/// direction of a snake that crossed it later.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Crossing {
+    pub pos: u16,
+    pub snake: u8,
+    /// The direction of the crossing snake's next segment, None while its head is on the cell.
+    pub mv: Option<Move>,
+}
+
 pub struct Bitboard<const S: usize, MODE: mode::Mode> {
     pub bodies: [MODE::Bitset; 3],
     pub snakes: [Snake; S],
+    /// The squad of each snake, given as the index of its squad's first snake.
+    /// Snakes without a squad form a squad of their own.
+    pub squads: [u8; S],
+    /// Cells that are shared by allies.
+    /// Empty unless allies share cells, so clones don't allocate.
+    pub crossings: Vec<Crossing>,
     pub food: MODE::Bitset,
+    /// Cells that hold at least one hazard.
     pub hazard_mask: MODE::Bitset,
-    pub hazard_dmg: i8,
+    /// Cells with stacked hazards, `stacked_hazards[i]` holds the cells with more than i+1 hazards.
+    pub stacked_hazards: [MODE::Bitset; MAX_HAZARD_STACK - 1],
+    /// The game's ruleset and its settings, as they were sent to us. The version is dropped, so
+    /// that boards stay cheap to clone.
+    pub ruleset: RulesetName,
+    pub settings: wire_rep::RulesetSettings,
     pub turn: u16,
     pub gamemode: Gamemode,
-    pub apply_moves: Arc<dyn Fn(&mut Self, &[Move; S]) + Send + Sync>,
+    pub mode: MODE,
+    /// Zobrist key of the position, kept up to date by the rules.
+    pub zobrist_key: u64,
+    pub rules: Rules,
+    /// Every bit that was flipped by the rules since the oldest outstanding `make_moves`.
+    undo_log: Vec<(u8, u16)>,
+}
+
+/// Everything that is needed to take back a joint move that was made with `make_moves`.
+#[derive(Clone)]
+pub struct Undo<const S: usize> {
+    snakes: [Snake; S],
+    crossings: Vec<Crossing>,
+    turn: u16,
+    zobrist_key: u64,
+    undo_log_len: usize,
+}
+
+// layers of the undo log, in addition to the body layers 0 to 2
+// hazard stack levels are logged as HAZARD_LAYER + level
+const FOOD_LAYER: u8 = 3;
+const HAZARD_LAYER: u8 = 4;
+
+/// Clones start with an empty undo log, because they can't take back the original's moves.
+/// This keeps clones for helper threads and pondering from copying the log.
+impl<const S: usize, MODE: Mode> Clone for Bitboard<S, MODE> {
+    fn clone(&self) -> Self {
+        Bitboard{
+            bodies: self.bodies,
+            snakes: self.snakes,
+            squads: self.squads,
+            crossings: self.crossings.clone(),
+            food: self.food,
+            hazard_mask: self.hazard_mask,
+            stacked_hazards: self.stacked_hazards,
+            ruleset: self.ruleset,
+            settings: self.settings,
+            turn: self.turn,
+            gamemode: self.gamemode,
+            mode: self.mode.clone(),
+            zobrist_key: self.zobrist_key,
+            rules: self.rules,
+            undo_log: Vec::new(),
+        }
+    }
+}
+
+/// Boards are equal if they hold the same position, the undo log and the mode are not compared.
+impl<const S: usize, MODE: Mode> PartialEq for Bitboard<S, MODE> {
+    fn eq(&self, other: &Self) -> bool {
+        self.bodies == other.bodies
+            && self.snakes == other.snakes
+            && self.squads == other.squads
+            && self.crossings == other.crossings
+            && self.food == other.food
+            && self.hazard_mask == other.hazard_mask
+            && self.stacked_hazards == other.stacked_hazards
+            && self.ruleset == other.ruleset
+            && self.settings == other.settings
+            && self.turn == other.turn
+            && self.gamemode == other.gamemode
+            && self.rules == other.rules
+            && self.zobrist_key == other.zobrist_key
+    }
 }
 
 impl<const S: usize, MODE: Mode> Hash for Bitboard<S, MODE> {
     fn hash<T: Hasher>(&self, state: &mut T) {
-        self.bodies.hash(state);
-        self.food.hash(state);
-        self.gamemode.hash(state);
-        self.hazard_dmg.hash(state);
-        for snake in self.snakes {
-            if snake.is_alive() {
-                snake.hash(state);
-            }
-        }
+        self.zobrist_key.hash(state);
     }
 }
 
 impl<const S: usize, MODE: mode::Mode> Bitboard<S, MODE> {
-    pub fn new() -> Self {
+    pub fn new(mode: MODE) -> Self {
         Bitboard{
             bodies: [MODE::Bitset::new(); 3],
             snakes: [Snake{head: 0, tail: 0, length: 0, health: 0, curled_bodyparts: 0}; S],
+            squads: std::array::from_fn(|i| i as u8),
+            crossings: Vec::new(),
             food: MODE::Bitset::new(),
             hazard_mask: MODE::Bitset::new(),
-            hazard_dmg: 14,
+            stacked_hazards: [MODE::Bitset::new(); MAX_HAZARD_STACK - 1],
+            ruleset: RulesetName::default(),
+            settings: wire_rep::RulesetSettings::default(),
             gamemode: Gamemode::Standard,
+            mode,
             turn: 0,
-            apply_moves: Arc::new(|_board, _mvs| {}),
+            zobrist_key: zobrist::game(Gamemode::Standard, 14),
+            rules: Rules::Standard,
+            undo_log: Vec::new(),
         }
     }
 
+    /// Applies a joint move and returns the record to take it back with `unmake_moves`.
+    pub fn make_moves(&mut self, moves: &[Move; S]) -> Undo<S> {
+        let undo = Undo{
+            snakes: self.snakes,
+            crossings: self.crossings.clone(),
+            turn: self.turn,
+            zobrist_key: self.zobrist_key,
+            undo_log_len: self.undo_log.len(),
+        };
+        rules::apply_moves(self, moves);
+        undo
+    }
+
+    /// Takes back the latest joint move that was made with `make_moves`.
+    pub fn unmake_moves(&mut self, undo: Undo<S>) {
+        while self.undo_log.len() > undo.undo_log_len {
+            let (layer, pos) = self.undo_log.pop().unwrap();
+            let bitset = match layer {
+                FOOD_LAYER => &mut self.food,
+                HAZARD_LAYER.. => self.hazard_layer_mut((layer - HAZARD_LAYER) as usize),
+                _ => &mut self.bodies[layer as usize],
+            };
+            bitset.set(pos as usize, !bitset.get(pos as usize));
+        }
+        self.snakes = undo.snakes;
+        self.crossings = undo.crossings;
+        self.turn = undo.turn;
+        self.zobrist_key = undo.zobrist_key;
+    }
+
+    /// Applies a joint move that is not going to be taken back.
+    pub fn play_moves(&mut self, moves: &[Move; S]) {
+        let undo_log_len = self.undo_log.len();
+        rules::apply_moves(self, moves);
+        self.undo_log.truncate(undo_log_len);
+    }
+
+    /// Spawns food like the game does between turns, using the caller's RNG.
+    /// The rules never do this on their own, so that a joint move always has the same outcome.
+    /// Spawned food is taken back by `unmake_moves` along with the latest joint move.
+    pub fn spawn_food(&mut self, rng: &mut impl rand::Rng) {
+        rules::spawn_food(self, rng);
+    }
+
     /// Deserializes a json move request string to a Bitboard.
     pub fn from_str(s: &str) -> Result<Self, serde_json::Error> {
         let state_result = serde_json::from_str::<wire_rep::GameState>(s);
@@ -181,16 +366,23 @@ impl<const S: usize, MODE: mode::Mode> Bitboard<S, MODE> {
         // food and hazards
         let mut wire_food = vec![];
         let mut wire_hazards = vec![];
-        for x in 0..MODE::W {
-            for y in 0..MODE::H {
-                if self.food.get(x+(y*MODE::W)) {
-                    wire_food.push(wire_rep::Coord{x: x.into(), y: y.into()});
+        for x in 0..self.mode.w() {
+            for y in 0..self.mode.h() {
+                if self.food.get(x+(y*self.mode.w())) {
+                    wire_food.push(wire_rep::Coord{x: x as i32, y: y as i32});
                 }
-                if self.hazard_mask.get(x+(y*MODE::W)) {
-                    wire_hazards.push(wire_rep::Coord{x: x.into(), y: y.into()});
+                for _ in 0..self.hazard_stack(x+(y*self.mode.w())) {
+                    wire_hazards.push(wire_rep::Coord{x: x as i32, y: y as i32});
                 }
             }
         }
+        if let Rules::Spiral{center} = self.rules {
+            // the spiral's center is read from the first hazard
+            let center = wire_rep::Coord{x: (center as usize % self.mode.w()) as i32, y: (center as usize / self.mode.w()) as i32};
+            if let Some(i) = wire_hazards.iter().position(|coord| *coord == center) {
+                wire_hazards.swap(0, i);
+            }
+        }
 
         // snakes
         let mut wire_snakes = vec![];
@@ -203,29 +395,27 @@ impl<const S: usize, MODE: mode::Mode> Bitboard<S, MODE> {
                 name: "".to_string(),
                 health: snake.health.into(),
                 length: snake.length.into(),
-                head: wire_rep::Coord{ x: (snake.head as usize % MODE::W).into(), y: (snake.head as usize / MODE::W).into() },
+                latency: None,
+                head: wire_rep::Coord{x: (snake.head as usize % self.mode.w()) as i32, y: (snake.head as usize / self.mode.w()) as i32},
                 shout: None,
-                squad: None,
+                squad: if (0..S).any(|j| self.is_ally(i, j)) {
+                    Some(self.squads[i].to_string())
+                } else {
+                    None
+                },
                 next_move: None,
                 body: vec![],
             };
             let mut tail_pos = snake.tail;
             while snake.head != tail_pos {
-                let next_pos = self.next_body_segment(tail_pos);
-                wire_snake.body.insert(0, wire_rep::Coord{x: (tail_pos as usize % MODE::W).into(), y: (tail_pos as usize / MODE::W).into()});
+                let next_pos = self.next_segment(i, tail_pos);
+                wire_snake.body.insert(0, wire_rep::Coord{x: (tail_pos as usize % self.mode.w()) as i32, y: (tail_pos as usize / self.mode.w()) as i32});
                 tail_pos = next_pos;
             }
-            wire_snake.body.insert(0, wire_rep::Coord{x: (tail_pos as usize % MODE::W).into(), y: (tail_pos as usize / MODE::W).into()});
+            wire_snake.body.insert(0, wire_rep::Coord{x: (tail_pos as usize % self.mode.w()) as i32, y: (tail_pos as usize / self.mode.w()) as i32});
             wire_snakes.push(wire_snake);
         }
 
-        // ruleset
-        let mut settings = serde_json::Map::<String, serde_json::Value>::default();
-        settings.insert("hazardDamagePerTurn".to_string(), self.hazard_dmg.into());
-        let mut ruleset = serde_json::Map::<String, serde_json::Value>::default();
-        ruleset.insert("name".to_string(), self.gamemode.get_ruleset_name().into());
-        ruleset.insert("settings".to_string(), settings.into());
-
         wire_rep::GameState{
             turn: self.turn.into(),
             game: wire_rep::Game{
@@ -233,12 +423,16 @@ impl<const S: usize, MODE: mode::Mode> Bitboard<S, MODE> {
                 timeout: 500,
                 source: "local".to_string(),
                 map: self.gamemode.get_map_name(),
-                ruleset,
+                ruleset: wire_rep::Ruleset{
+                    name: self.ruleset,
+                    version: String::new(),
+                    settings: self.settings,
+                },
             },
             you: wire_snakes[0].clone(),
             board: wire_rep::Board{
-                height: MODE::H,
-                width: MODE::W,
+                height: self.mode.h(),
+                width: self.mode.w(),
                 snakes: wire_snakes,
                 food: wire_food,
                 hazards: wire_hazards,
@@ -246,28 +440,32 @@ impl<const S: usize, MODE: mode::Mode> Bitboard<S, MODE> {
         }
     }
 
+    /// Creates a bitboard from a gamestate, which must have passed `GameState::validate`.
     pub fn from_gamestate(state: wire_rep::GameState) -> Self {
-        let mut board = Self::new();
-        rules::attach_rules(&mut board, &state);
+        let mut board = Self::new(MODE::with_dimensions(state.board.width, state.board.height, is_wrapped(&state)));
+        board.rules = Rules::from_gamestate(&state);
         board.gamemode = Gamemode::from_gamestate(&state);
         board.turn = state.turn as u16;
-        if let Some(settings) = state.game.ruleset.get("settings") {
-            board.hazard_dmg = if let Some(x) = settings["hazardDamagePerTurn"].as_i64() {
-                x as i8
-            } else {
-                14
-            };
-        }
+        board.ruleset = state.game.ruleset.name;
+        board.settings = state.game.ruleset.settings;
+        let w = board.mode.w();
+        let index = |coord: &wire_rep::Coord| w*coord.y as usize + coord.x as usize;
         for food in state.board.food {
-            board.food.set_bit(MODE::W*food.y + food.x);
+            board.food.set_bit(index(&food));
         }
         for hazard in state.board.hazards {
-            if hazard.y < MODE::H && hazard.x < MODE::W {
-                board.hazard_mask.set_bit(MODE::W*hazard.y + hazard.x);
+            // every occurrence of a cell in the list adds a hazard to its stack
+            let pos = index(&hazard);
+            let stack = board.hazard_stack(pos) as usize;
+            if stack < MAX_HAZARD_STACK {
+                board.hazard_layer_mut(stack).set_bit(pos);
             }
         }
         let mut m = 0;
         let mut n;
+        let mut squad_names = vec![None; S];
+        // allies can share cells, where the earlier snake keeps the direction bits
+        let mut occupied = board.bodies[0];
         for snake in state.board.snakes {
             if snake.head == state.you.head {
                 n = 0;
@@ -275,55 +473,225 @@ impl<const S: usize, MODE: mode::Mode> Bitboard<S, MODE> {
                 m += 1;
                 n = m;
             }
+            squad_names[n] = snake.squad.filter(|squad| !squad.is_empty());
             board.snakes[n].health = snake.health as i8;
             board.snakes[n].length = snake.length as u8;
-            board.snakes[n].head = (MODE::W*snake.head.y) as u16 + snake.head.x as u16;
-            board.snakes[n].tail = (MODE::W*snake.body[snake.body.len()-1].y) as u16 + snake.body[snake.body.len()-1].x as u16;
+            board.snakes[n].head = index(&snake.head) as u16;
+            board.snakes[n].tail = index(&snake.body[snake.body.len()-1]) as u16;
             let mut prev_pos = board.snakes[n].head;
             let mut pos;
-            board.bodies[0].set_bit(MODE::W*snake.head.y + snake.head.x);
+            let others = occupied;
+            if others.get(index(&snake.head)) {
+                board.crossings.push(Crossing{ pos: prev_pos, snake: n as u8, mv: None });
+            }
+            board.bodies[0].set_bit(index(&snake.head));
+            occupied.set_bit(index(&snake.head));
             for bod in snake.body[1..].iter() {
-                pos = (MODE::W*bod.y + bod.x) as u16;
+                pos = index(bod) as u16;
                 if pos == prev_pos {
                     board.snakes[n].curled_bodyparts += 1;
                     continue
                 }    
                 board.bodies[0].set_bit(pos as usize);
-                if pos == prev_pos + 1 || pos == prev_pos + MODE::W as u16 || MODE::WRAP && prev_pos == pos + MODE::W as u16 - 1 || MODE::WRAP && prev_pos == pos + (MODE::H as u16 - 1) * MODE::W as u16 {
-                    board.bodies[1].set_bit(pos as usize);
-                }
-                if prev_pos == pos + 1 || prev_pos + 1 == pos || MODE::WRAP && prev_pos == pos + MODE::W as u16 - 1 || MODE::WRAP && prev_pos + MODE::W as u16 - 1 == pos {
-                    board.bodies[2].set_bit(pos as usize);
+                occupied.set_bit(pos as usize);
+                let bit_1 = pos == prev_pos + 1 || pos == prev_pos + board.mode.w() as u16 || board.mode.wrap() && prev_pos == pos + board.mode.w() as u16 - 1 || board.mode.wrap() && prev_pos == pos + (board.mode.h() as u16 - 1) * board.mode.w() as u16;
+                let bit_2 = prev_pos == pos + 1 || prev_pos + 1 == pos || board.mode.wrap() && prev_pos == pos + board.mode.w() as u16 - 1 || board.mode.wrap() && prev_pos + board.mode.w() as u16 - 1 == pos;
+                let mv = Move::from_int(bit_1 as u8 | (bit_2 as u8) << 1);
+                if others.get(pos as usize) {
+                    board.crossings.push(Crossing{ pos, snake: n as u8, mv: Some(mv) });
+                } else {
+                    board.bodies[1].set(pos as usize, bit_1);
+                    board.bodies[2].set(pos as usize, bit_2);
                 }
                 prev_pos = pos;
             }
-            if board.snakes[n].curled_bodyparts == 0 && board.gamemode != Gamemode::Constrictor {
-                board.bodies[0].unset_bit(board.snakes[n].tail as usize);
+        }
+        // tails are free to move into, unless they are shared
+        for i in 0..S {
+            let snake = board.snakes[i];
+            if snake.is_alive() && snake.curled_bodyparts == 0 && board.rules != Rules::Constrictor && !board.is_shared(snake.tail) {
+                board.bodies[0].unset_bit(snake.tail as usize);
+            }
+        }
+        for i in 0..S {
+            if squad_names[i].is_some() {
+                board.squads[i] = squad_names.iter().position(|name| *name == squad_names[i]).unwrap() as u8;
             }
         }
+        board.zobrist_key = board.compute_zobrist_key();
         board
     }
 
-    /// Returns true if self is dead or the only one alive
+    /// Computes the zobrist key of this position from scratch.
+    pub fn compute_zobrist_key(&self) -> u64 {
+        let mut key = zobrist::game(self.gamemode, self.settings.hazard_damage_per_turn);
+        for pos in 0..self.mode.n() {
+            for layer in 0..3 {
+                if self.bodies[layer].get(pos) {
+                    key ^= zobrist::body(layer, pos);
+                }
+            }
+            if self.food.get(pos) {
+                key ^= zobrist::food(pos);
+            }
+            for level in 0..MAX_HAZARD_STACK {
+                if self.hazard_layer(level).get(pos) {
+                    key ^= zobrist::hazard(level, pos);
+                }
+            }
+        }
+        for (i, snake) in self.snakes.iter().enumerate() {
+            key ^= zobrist::snake(i, snake);
+        }
+        key
+    }
+
+    /// Sets a bit in one of the body layers and updates the zobrist key and undo log.
+    #[inline(always)]
+    fn set_body(&mut self, layer: usize, pos: usize, value: bool) {
+        if self.bodies[layer].get(pos) != value {
+            self.bodies[layer].set(pos, value);
+            self.zobrist_key ^= zobrist::body(layer, pos);
+            self.undo_log.push((layer as u8, pos as u16));
+        }
+    }
+
+    /// Sets a food bit and updates the zobrist key and undo log.
+    #[inline(always)]
+    fn set_food(&mut self, pos: usize, value: bool) {
+        if self.food.get(pos) != value {
+            self.food.set(pos, value);
+            self.zobrist_key ^= zobrist::food(pos);
+            self.undo_log.push((FOOD_LAYER, pos as u16));
+        }
+    }
+
+    /// Sets a bit in one of the hazard stack levels and updates the zobrist key and undo log.
+    #[inline(always)]
+    fn set_hazard(&mut self, level: usize, pos: usize, value: bool) {
+        if self.hazard_layer(level).get(pos) != value {
+            self.hazard_layer_mut(level).set(pos, value);
+            self.zobrist_key ^= zobrist::hazard(level, pos);
+            self.undo_log.push((HAZARD_LAYER + level as u8, pos as u16));
+        }
+    }
+
+    /// Removes all hazards from a cell.
+    fn clear_hazards(&mut self, pos: usize) {
+        for level in (0..self.hazard_stack(pos) as usize).rev() {
+            self.set_hazard(level, pos, false);
+        }
+    }
+
+    /// Puts another hazard on top of a cell's stack.
+    fn add_hazard(&mut self, pos: usize) {
+        let stack = self.hazard_stack(pos) as usize;
+        if stack < MAX_HAZARD_STACK {
+            self.set_hazard(stack, pos, true);
+        }
+    }
+
+    /// Returns the cells that hold more than level hazards.
+    #[inline(always)]
+    fn hazard_layer(&self, level: usize) -> &MODE::Bitset {
+        if level == 0 {
+            &self.hazard_mask
+        } else {
+            &self.stacked_hazards[level - 1]
+        }
+    }
+
+    #[inline(always)]
+    fn hazard_layer_mut(&mut self, level: usize) -> &mut MODE::Bitset {
+        if level == 0 {
+            &mut self.hazard_mask
+        } else {
+            &mut self.stacked_hazards[level - 1]
+        }
+    }
+
+    /// Returns the number of hazards stacked on a cell.
+    #[inline(always)]
+    pub fn hazard_stack(&self, pos: usize) -> u8 {
+        if !self.hazard_mask.get(pos) {
+            return 0
+        }
+        1 + self.stacked_hazards.iter().take_while(|layer| layer.get(pos)).count() as u8
+    }
+
+    /// Returns the damage that the hazards on a cell deal per turn.
+    #[inline(always)]
+    pub fn hazard_damage(&self, pos: usize) -> i16 {
+        self.settings.hazard_damage_per_turn as i16 * self.hazard_stack(pos) as i16
+    }
+
+    /// Returns the cells that hold at least n hazards, n must be at least 1.
+    pub fn hazards_at_least(&self, n: usize) -> MODE::Bitset {
+        if n > MAX_HAZARD_STACK {
+            MODE::Bitset::new()
+        } else {
+            *self.hazard_layer(n - 1)
+        }
+    }
+
+    /// Returns the cells on which the stacked hazards deal at least dmg damage per turn.
+    pub fn hazards_dealing(&self, dmg: i16) -> MODE::Bitset {
+        let hazard_dmg = self.settings.hazard_damage_per_turn as i16;
+        if hazard_dmg <= 0 {
+            return MODE::Bitset::new()
+        }
+        self.hazards_at_least(((dmg + hazard_dmg - 1) / hazard_dmg).max(1) as usize)
+    }
+
+    /// Modifies a snake and updates the zobrist key.
+    #[inline(always)]
+    fn update_snake<R>(&mut self, snake_index: usize, f: impl FnOnce(&mut Snake) -> R) -> R {
+        self.zobrist_key ^= zobrist::snake(snake_index, &self.snakes[snake_index]);
+        let result = f(&mut self.snakes[snake_index]);
+        self.zobrist_key ^= zobrist::snake(snake_index, &self.snakes[snake_index]);
+        result
+    }
+
+    /// Returns true if the two snakes are distinct members of the same squad.
+    #[inline(always)]
+    pub fn is_ally(&self, i: usize, j: usize) -> bool {
+        i != j && self.squads[i] == self.squads[j]
+    }
+
+    /// Returns true if self is dead or only self and its allies are alive.
+    /// Solo games only end with our death.
     pub fn is_terminal(&self) -> bool {
         if self.snakes[0].is_dead() {
             return true
         }
+        if self.gamemode == Gamemode::Solo {
+            return false
+        }
         for i in 1..S {
-            if self.snakes[i].is_alive() {
+            if self.snakes[i].is_alive() && !self.is_ally(0, i) {
                 return false
             }
         }
         true
     }
 
-    /// Returns the wdl value of the position, if it is terminal
+    /// Returns the wdl value of the position from our squad's point of view, if it is terminal.
+    /// If we are dead, but allies and enemies are still alive, the game is scored as a draw.
+    /// Solo games can't be won, they are lost once we die.
     pub fn win_draw_loss(&self) -> Option<i8> {
         let me_alive = self.snakes[0].is_alive();
+        if self.gamemode == Gamemode::Solo {
+            return if me_alive { None } else { Some(-1) }
+        }
         let mut enemies_alive = 0;
+        let mut allies_alive = 0;
         for i in 1..S {
             if self.snakes[i].is_alive() {
-                enemies_alive += 1;
+                if self.is_ally(0, i) {
+                    allies_alive += 1;
+                } else {
+                    enemies_alive += 1;
+                }
             }
         }
         if me_alive {
@@ -333,7 +701,9 @@ impl<const S: usize, MODE: mode::Mode> Bitboard<S, MODE> {
                 None
             }
         } else {
-            if enemies_alive != 0 {
+            if allies_alive != 0 && enemies_alive == 0 {
+                Some(1)
+            } else if allies_alive == 0 && enemies_alive != 0 {
                 Some(-1)
             } else {
                 Some(0)
@@ -342,18 +712,18 @@ impl<const S: usize, MODE: mode::Mode> Bitboard<S, MODE> {
     }
 
     pub fn distance(&self, from: u16, to: u16) -> u16 {
-        let w = MODE::W as u16;
+        let w = self.mode.w() as u16;
         let dist_x = (from%w).max(to%w) - (from%w).min(to%w);
         let dist_y = (from/w).max(to/w) - (from/w).min(to/w);
-        if MODE::WRAP {
-            dist_x.min(w - dist_x) + dist_y.min(MODE::H as u16 - dist_y)
+        if self.mode.wrap() {
+            dist_x.min(w - dist_x) + dist_y.min(self.mode.h() as u16 - dist_y)
         } else {
             dist_x + dist_y
         }
     }
 
     pub fn is_legal_move(&self, from: u16, mv: Move) -> bool {
-        MODE::WRAP || None != MODE::moves_from_position(from)[mv.to_int() as usize]
+        self.mode.wrap() || None != self.mode.moves_from_position(from)[mv.to_int() as usize]
     }
 
     pub fn is_legal_enemy_moves(&self, mvs: [Move; S]) -> bool {
@@ -370,64 +740,114 @@ impl<const S: usize, MODE: mode::Mode> Bitboard<S, MODE> {
         if S <= 2 || self.snakes[0].is_dead() {
             return  // this is a terminal state, so we can ignore the dead body
         }
-        let snake = &self.snakes[snake_index];
+        let snake = self.snakes[snake_index];
         let mut tail_pos = snake.tail;
         let mut debug_counter = 0;
         while snake.head != tail_pos {
             debug_counter += 1;
             debug_assert!(debug_counter < 10000, "endless loop in remove_snake_body\n{:?}", self);
-            let next_pos = self.next_body_segment(tail_pos);
-            self.bodies[0].unset_bit(tail_pos as usize);
-            self.bodies[1].unset_bit(tail_pos as usize);
-            self.bodies[2].unset_bit(tail_pos as usize);
+            let next_pos = self.next_segment(snake_index, tail_pos);
+            self.vacate(snake_index, tail_pos);
             tail_pos = next_pos;
         }
+        // the head may still be on an ally's body
+        self.crossings.retain(|crossing| crossing.snake as usize != snake_index);
+    }
+
+    /// Takes a snake's segment off a cell. A cell that is shared with an ally stays occupied, and
+    /// keeps or takes over the direction bits of the ally.
+    fn vacate(&mut self, snake_index: usize, pos: u16) {
+        if let Some(k) = self.crossings.iter().position(|c| c.pos == pos && c.snake as usize == snake_index) {
+            self.crossings.remove(k);
+        } else if let Some(k) = self.crossings.iter().position(|c| c.pos == pos) {
+            let mv_int = self.crossings.remove(k).mv.map_or(0, |mv| mv.to_int());
+            self.set_body(1, pos as usize, (mv_int&1) != 0);
+            self.set_body(2, pos as usize, (mv_int>>1) != 0);
+        } else {
+            self.set_body(0, pos as usize, false);
+            self.set_body(1, pos as usize, false);
+            self.set_body(2, pos as usize, false);
+        }
+    }
+
+    /// Returns true if more than one snake occupies the cell.
+    pub fn is_shared(&self, pos: u16) -> bool {
+        self.crossings.iter().any(|c| c.pos == pos)
+    }
+
+    /// Gets the next segment of a snake's body in head direction, which also works on cells that
+    /// are shared with allies.
+    pub fn next_segment(&self, snake_index: usize, pos: u16) -> u16 {
+        let crossing = self.crossings.iter().find(|c| c.pos == pos && c.snake as usize == snake_index);
+        match crossing.and_then(|c| c.mv) {
+            Some(mv) => self.step(pos, mv.to_int()),
+            None => self.next_body_segment(pos),
+        }
+    }
+
+    /// Returns true if the cell holds one of the snake's body parts, not counting its head.
+    fn is_on_body(&self, snake_index: usize, pos: u16) -> bool {
+        let snake = self.snakes[snake_index];
+        let mut tail_pos = snake.tail;
+        for _ in 0..self.mode.n() {
+            if tail_pos == snake.head {
+                return false
+            } else if tail_pos == pos {
+                return true
+            }
+            tail_pos = self.next_segment(snake_index, tail_pos);
+        }
+        false
     }
 
     // Gets next segment in snake body in head direction.
     // Does not check if pos is actually on a snake.
     pub fn next_body_segment(&self, pos: u16) -> u16 {
         let move_int = self.bodies[1].get(pos as usize) as u8 | (self.bodies[2].get(pos as usize) as u8) << 1;
-        if MODE::WRAP {
-            (pos as i16 + Move::int_to_index_wrapping(move_int, MODE::W, MODE::H, pos)) as u16
+        self.step(pos, move_int)
+    }
+
+    fn step(&self, pos: u16, move_int: u8) -> u16 {
+        if self.mode.wrap() {
+            (pos as i16 + Move::int_to_index_wrapping(move_int, self.mode.w(), self.mode.h(), pos)) as u16
         } else {
-            (pos as i16 + Move::int_to_index(move_int, MODE::W)) as u16
+            (pos as i16 + Move::int_to_index(move_int, self.mode.w())) as u16
         }
     }
 
     pub fn kill_snake(&mut self, snake_index: usize) {
-        self.snakes[snake_index].health = -1;
+        self.update_snake(snake_index, |snake| snake.health = -1);
         self.remove_snake_body(snake_index);
     }
 
     fn coord_string_from_index(&self, idx: u16) -> String {
-        let x = idx % MODE::W as u16;
-        let y = idx / MODE::W as u16;
+        let x = idx % self.mode.w() as u16;
+        let y = idx / self.mode.w() as u16;
         "(".to_string() + &x.to_string() + " " + &y.to_string() + ")"
     }
 }
 
 impl<const S: usize, MODE: Mode> std::fmt::Debug for Bitboard<S, MODE> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let W: usize = MODE::W;
-        let H: usize = MODE::H;
+        let w: usize = self.mode.w();
+        let h: usize = self.mode.h();
 
         // draw the board
-        for i in 0..H {
-            for j in 0..W {
+        for i in 0..h {
+            for j in 0..w {
                 let mut head_str = None;
-                if self.snakes[0].head as usize == (W*(H-1-i))+j {
+                if self.snakes[0].head as usize == (w*(h-1-i))+j {
                     head_str = Some("@");
                 } else {
                     for snake in self.snakes[1..].iter() {
-                        if snake.head as usize == (W*(H-1-i))+j {
+                        if snake.head as usize == (w*(h-1-i))+j {
                             head_str = Some("E");
                         }
                     }
                 }
-                let mut tile = if self.bodies[0].get((W*(H-1-i))+j) { "x" } else if let Some(s) = head_str { s } else { "." }.to_string();
-                tile.push_str(if self.bodies[2].get((W*(H-1-i))+j) { "x" } else if let Some(s) = head_str { s } else { "." });
-                tile.push_str(if self.bodies[1].get((W*(H-1-i))+j) { "x" } else if let Some(s) = head_str { s } else { "." });
+                let mut tile = if self.bodies[0].get((w*(h-1-i))+j) { "x" } else if let Some(s) = head_str { s } else { "." }.to_string();
+                tile.push_str(if self.bodies[2].get((w*(h-1-i))+j) { "x" } else if let Some(s) = head_str { s } else { "." });
+                tile.push_str(if self.bodies[1].get((w*(h-1-i))+j) { "x" } else if let Some(s) = head_str { s } else { "." });
                 f.write_str(&format!("{} ", tile))?;
             }
             f.write_str("\n")?;
@@ -451,8 +871,8 @@ impl<const S: usize, MODE: Mode> std::fmt::Debug for Bitboard<S, MODE> {
 
 impl<const S: usize, MODE: Mode> std::fmt::Display for Bitboard<S, MODE> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let W: usize = MODE::W;
-        let H: usize = MODE::H;
+        let w: usize = self.mode.w();
+        let h: usize = self.mode.h();
 
         // decide on colors for the individual snakes
         let colors = [Color::Red, Color::Green, Color::Cyan, Color::Yellow, Color::Blue, Color::Magenta];
@@ -461,40 +881,41 @@ impl<const S: usize, MODE: Mode> std::fmt::Display for Bitboard<S, MODE> {
             let mut tail_pos = snake.tail;
             while snake.head != tail_pos {
                 snake_colors.insert(tail_pos as usize, colors[i % colors.len()]);
-                let move_int = self.bodies[1].get(tail_pos as usize) as u8 | (self.bodies[2].get(tail_pos as usize) as u8) << 1;
-                tail_pos = if MODE::WRAP {
-                    tail_pos as i16 + Move::int_to_index_wrapping(move_int, W, H, tail_pos)
-                } else {
-                    tail_pos as i16 + Move::int_to_index(move_int, W)
-                } as u16;
+                tail_pos = self.next_segment(i, tail_pos);
             }
             snake_colors.insert(tail_pos as usize, colors[i % colors.len()]);
         }
 
         // draw the board
-        for i in 0..H {
-            for j in 0..W {
+        for i in 0..h {
+            for j in 0..w {
                 let mut head_str = None;
-                if self.snakes[0].head as usize == (W*(H-1-i))+j {
+                if self.snakes[0].head as usize == (w*(h-1-i))+j {
                     head_str = Some("@");
                 } else {
                     for snake in self.snakes[1..].iter() {
-                        if snake.head as usize == (W*(H-1-i))+j {
+                        if snake.head as usize == (w*(h-1-i))+j {
                             head_str = Some("E");
                         }
                     }
                 }
-                let mut tile = if self.bodies[0].get((W*(H-1-i))+j) { "x" } else if let Some(s) = head_str { s } else { "." }.to_string();
-                tile.push_str(if self.bodies[2].get((W*(H-1-i))+j) { "x" } else if let Some(s) = head_str { s } else { "." });
-                tile.push_str(if self.bodies[1].get((W*(H-1-i))+j) { "x" } else if let Some(s) = head_str { s } else { "." });
+                let mut tile = if self.bodies[0].get((w*(h-1-i))+j) { "x" } else if let Some(s) = head_str { s } else { "." }.to_string();
+                tile.push_str(if self.bodies[2].get((w*(h-1-i))+j) { "x" } else if let Some(s) = head_str { s } else { "." });
+                tile.push_str(if self.bodies[1].get((w*(h-1-i))+j) { "x" } else if let Some(s) = head_str { s } else { "." });
+                let stack = self.hazard_stack(w*(h-1-i)+j);
+                if stack > 1 && tile == "..." {
+                    tile = format!(".{}.", stack);
+                }
                 let mut colored_tile = tile.color(Color::BrightWhite);
-                if self.hazard_mask.get(W*(H-1-i)+j) {
+                if stack > 1 {
+                    colored_tile = tile.on_color(Color::BrightBlack);
+                } else if stack == 1 {
                     colored_tile = tile.on_color(Color::White);
                 }
-                if self.food.get((W*(H-1-i))+j) {
+                if self.food.get((w*(h-1-i))+j) {
                     colored_tile = tile.on_color(Color::Magenta);
                 }
-                if let Some(c) = snake_colors.get(&((W*(H-1-i))+j)) {
+                if let Some(c) = snake_colors.get(&((w*(h-1-i))+j)) {
                     colored_tile = colored_tile.color(*c);
                 }
                 f.write_str(&format!("{} ", colored_tile))?;
@@ -519,10 +940,17 @@ impl<const S: usize, MODE: Mode> std::fmt::Display for Bitboard<S, MODE> {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
     use crate::wire_rep;
     use test::Bencher;
+    use rand_pcg::Pcg64Mcg;
+
+    /// A quiet early position of two snakes on an open standard board, for tests that only need some game to search.
+    pub(crate) fn create_duel_board() -> Bitboard<2, mode::Standard> {
+        let val = r###"{"game":{"id":"","ruleset":{"name":"standard","version":"?","settings":{}},"map":"standard","timeout":500,"source":""},"turn":10,"board":{"width":11,"height":11,"food":[{"x":5,"y":5}],"hazards":[],"snakes":[{"id":"a","name":"","health":90,"body":[{"x":1,"y":1},{"x":1,"y":2},{"x":1,"y":3}],"head":{"x":1,"y":1},"length":3,"shout":"","squad":""},{"id":"b","name":"","health":90,"body":[{"x":9,"y":9},{"x":9,"y":8},{"x":9,"y":7}],"head":{"x":9,"y":9},"length":3,"shout":"","squad":""}]},"you":{"id":"a","name":"","health":90,"body":[{"x":1,"y":1},{"x":1,"y":2},{"x":1,"y":3}],"head":{"x":1,"y":1},"length":3,"shout":"","squad":""}}"###;
+        Bitboard::<2, mode::Standard>::from_str(val).unwrap()
+    }
 
     fn create_board() -> Bitboard<4, mode::StandardWrapped> {
         let val = r###"{"game":{"id":"7ddd5c60-e27a-42ae-985e-f056e5695836","ruleset":{"name":"wrapped","version":"?","settings":{"foodSpawnChance":15,"minimumFood":1,"hazardDamagePerTurn":100,"royale":{},"squad":{"allowBodyCollisions":false,"sharedElimination":false,"sharedHealth":false,"sharedLength":false}}},"map":"hz_islands_bridges","timeout":500,"source":"league"},"turn":445,"board":{"width":11,"height":11,"food":[{"x":1,"y":9},{"x":1,"y":8},{"x":9,"y":1},{"x":6,"y":3},{"x":7,"y":3},{"x":7,"y":4},{"x":8,"y":3},{"x":4,"y":9},{"x":10,"y":8},{"x":6,"y":6}],"hazards":[{"x":5,"y":10},{"x":5,"y":9},{"x":5,"y":7},{"x":5,"y":6},{"x":5,"y":5},{"x":5,"y":4},{"x":5,"y":3},{"x":5,"y":0},{"x":5,"y":1},{"x":6,"y":5},{"x":7,"y":5},{"x":9,"y":5},{"x":10,"y":5},{"x":4,"y":5},{"x":3,"y":5},{"x":1,"y":5},{"x":0,"y":5},{"x":1,"y":10},{"x":9,"y":10},{"x":1,"y":0},{"x":9,"y":0},{"x":10,"y":1},{"x":10,"y":0},{"x":10,"y":10},{"x":10,"y":9},{"x":0,"y":10},{"x":0,"y":9},{"x":0,"y":1},{"x":0,"y":0},{"x":0,"y":6},{"x":0,"y":4},{"x":10,"y":6},{"x":10,"y":4},{"x":6,"y":10},{"x":4,"y":10},{"x":6,"y":0},{"x":4,"y":0}],"snakes":[{"id":"gs_P3P9rW63VPgMcYFFJ9R6McrM","name":"Shapeshifter","health":91,"body":[{"x":6,"y":2},{"x":6,"y":1},{"x":7,"y":1},{"x":7,"y":0},{"x":7,"y":10},{"x":8,"y":10},{"x":8,"y":0},{"x":8,"y":1},{"x":8,"y":2},{"x":9,"y":2},{"x":9,"y":3},{"x":10,"y":3},{"x":10,"y":2},{"x":0,"y":2},{"x":0,"y":3},{"x":1,"y":3},{"x":1,"y":4},{"x":2,"y":4},{"x":3,"y":4},{"x":3,"y":3},{"x":2,"y":3},{"x":2,"y":2},{"x":1,"y":2},{"x":1,"y":1},{"x":2,"y":1},{"x":2,"y":0},{"x":3,"y":0},{"x":3,"y":1},{"x":4,"y":1},{"x":4,"y":2}],"latency":11,"head":{"x":6,"y":2},"length":30,"shout":"","squad":"","customizations":{"color":"#900050","head":"cosmic-horror-special","tail":"cosmic-horror"}},{"id":"gs_YMFKJHvJwS9VV7SgtTMVmKVQ","name":"🇺🇦 Jagwire 🇺🇦","health":76,"body":[{"x":9,"y":9},{"x":8,"y":9},{"x":7,"y":9},{"x":6,"y":9},{"x":6,"y":8},{"x":5,"y":8},{"x":4,"y":8},{"x":3,"y":8},{"x":3,"y":9},{"x":3,"y":10},{"x":2,"y":10},{"x":2,"y":9},{"x":2,"y":8},{"x":2,"y":7},{"x":3,"y":7},{"x":4,"y":7},{"x":4,"y":6},{"x":3,"y":6},{"x":2,"y":6},{"x":1,"y":6},{"x":1,"y":7},{"x":0,"y":7},{"x":10,"y":7},{"x":9,"y":7},{"x":9,"y":6},{"x":8,"y":6},{"x":7,"y":6},{"x":7,"y":7},{"x":7,"y":8},{"x":8,"y":8},{"x":9,"y":8}],"latency":23,"head":{"x":9,"y":9},"length":31,"shout":"","squad":"","customizations":{"color":"#ffd900","head":"smile","tail":"wave"}}]},"you":{"id":"gs_P3P9rW63VPgMcYFFJ9R6McrM","name":"Shapeshifter","health":91,"body":[{"x":6,"y":2},{"x":6,"y":1},{"x":7,"y":1},{"x":7,"y":0},{"x":7,"y":10},{"x":8,"y":10},{"x":8,"y":0},{"x":8,"y":1},{"x":8,"y":2},{"x":9,"y":2},{"x":9,"y":3},{"x":10,"y":3},{"x":10,"y":2},{"x":0,"y":2},{"x":0,"y":3},{"x":1,"y":3},{"x":1,"y":4},{"x":2,"y":4},{"x":3,"y":4},{"x":3,"y":3},{"x":2,"y":3},{"x":2,"y":2},{"x":1,"y":2},{"x":1,"y":1},{"x":2,"y":1},{"x":2,"y":0},{"x":3,"y":0},{"x":3,"y":1},{"x":4,"y":1},{"x":4,"y":2}],"latency":11,"head":{"x":6,"y":2},"length":30,"shout":"","squad":"","customizations":{"color":"#900050","head":"cosmic-horror-special","tail":"cosmic-horror"}}}"###;
@@ -539,14 +967,563 @@ mod tests {
         assert_eq!(board.bodies, copy.bodies);
         assert_eq!(board.turn, copy.turn);
         assert_eq!(board.gamemode, copy.gamemode);
+        assert_eq!(board.rules, copy.rules);
+    }
+
+    #[test]
+    fn test_ruleset_settings() {
+        let state = |ruleset: &str| format!(
+            r###"{{"game":{{"id":"","ruleset":{},"map":"standard","timeout":500,"source":""}},"turn":10,"board":{{"width":11,"height":11,"food":[],"hazards":[],"snakes":[{{"id":"a","name":"","health":90,"body":[{{"x":1,"y":1}},{{"x":1,"y":2}}],"head":{{"x":1,"y":1}},"length":2,"shout":"","squad":""}}]}},"you":{{"id":"a","name":"","health":90,"body":[{{"x":1,"y":1}},{{"x":1,"y":2}}],"head":{{"x":1,"y":1}},"length":2,"shout":"","squad":""}}}}"###,
+            ruleset,
+        );
+
+        // missing settings take the game's defaults
+        let board = Bitboard::<1, mode::Standard>::from_str(&state(r#"{"name":"standard"}"#)).unwrap();
+        assert_eq!(board.settings, wire_rep::RulesetSettings::default());
+        assert_eq!(board.hazard_damage(0), 0);
+
+        let board = Bitboard::<1, mode::Standard>::from_str(&state(r#"{"name":"royale","version":"v1.2.3","settings":{"hazardDamagePerTurn":-20,"royale":{"shrinkEveryNTurns":5}}}"#)).unwrap();
+        assert_eq!(board.ruleset, RulesetName::Royale);
+        assert_eq!(board.settings.hazard_damage_per_turn, -20);
+        assert_eq!(board.settings.royale.shrink_every_n_turns, 5);
+        let copy = Bitboard::<1, mode::Standard>::from_str(&board.to_string().unwrap()).unwrap();
+        assert_eq!((copy.ruleset, copy.settings), (board.ruleset, board.settings));
+
+        // unknown names are played by the standard rules
+        let board = Bitboard::<1, mode::Standard>::from_str(&state(r#"{"name":"chess"}"#)).unwrap();
+        assert_eq!((board.ruleset, board.rules, board.gamemode), (RulesetName::Unknown, Rules::Standard, Gamemode::Standard));
+
+        // out of range values are errors instead of silent defaults
+        for ruleset in [
+            r#"{"name":"standard","settings":{"hazardDamagePerTurn":200}}"#,
+            r#"{"name":"standard","settings":{"foodSpawnChance":150}}"#,
+            r#"{"name":"standard","settings":{"minimumFood":-1}}"#,
+            r#"{"name":"standard","settings":{"squad":{"sharedHealth":"yes"}}}"#,
+        ] {
+            assert!(Bitboard::<1, mode::Standard>::from_str(&state(ruleset)).is_err(), "{}", ruleset);
+        }
+    }
+
+    #[test]
+    fn test_validate_gamestate() {
+        let state = |snake: &str, you: &str, food: &str| serde_json::from_str::<wire_rep::GameState>(&format!(
+            r###"{{"game":{{"id":"","ruleset":{{"name":"standard"}},"map":"standard","timeout":500,"source":""}},"turn":10,"board":{{"width":11,"height":11,"food":[{}],"hazards":[],"snakes":[{{"id":"a","name":"","health":90,{},"length":2}}]}},"you":{{"id":"{}","name":"","health":90,"body":[{{"x":1,"y":1}},{{"x":1,"y":2}}],"head":{{"x":1,"y":1}},"length":2}}}}"###,
+            food, snake, you,
+        )).unwrap();
+        let body = r#""body":[{"x":1,"y":1},{"x":1,"y":2}],"head":{"x":1,"y":1}"#;
+        assert_eq!(state(body, "a", r#"{"x":10,"y":10}"#).validate(), Ok(()));
+
+        // negative coordinates parse, so that they can be reported
+        let errors = state(body, "a", r#"{"x":-1,"y":3},{"x":4,"y":11}"#).validate().unwrap_err();
+        assert_eq!(errors, vec![
+            wire_rep::ValidationError::OutOfBounds{field: "food[0]".to_string(), x: -1, y: 3},
+            wire_rep::ValidationError::OutOfBounds{field: "food[1]".to_string(), x: 4, y: 11},
+        ]);
+        assert_eq!(errors[0].to_string(), "food[0] at (-1, 3) lies outside the board");
+
+        for (snake, error) in [
+            (r#""body":[],"head":{"x":1,"y":1}"#, wire_rep::ValidationError::EmptyBody{snake: "a".to_string()}),
+            (r#""body":[{"x":1,"y":2},{"x":1,"y":1}],"head":{"x":1,"y":1}"#, wire_rep::ValidationError::HeadNotOnBody{snake: "a".to_string()}),
+            (r#""body":[{"x":1,"y":1},{"x":1,"y":3}],"head":{"x":1,"y":1}"#, wire_rep::ValidationError::DetachedBody{snake: "a".to_string(), segment: 1}),
+        ] {
+            assert_eq!(state(snake, "a", "").validate(), Err(vec![error]));
+        }
+        let moved = r#""body":[{"x":2,"y":1},{"x":1,"y":1}],"head":{"x":2,"y":1}"#;
+        assert_eq!(state(moved, "b", "").validate(), Err(vec![wire_rep::ValidationError::UnknownYou{id: "b".to_string()}]));
+        // we are recognized by our id and head
+        assert_eq!(state(body, "b", "").validate(), Err(vec![wire_rep::ValidationError::UnknownYou{id: "b".to_string()}]));
+
+        // out of range health and length parse, so that they can be reported
+        let mut invalid = state(body, "a", "");
+        invalid.board.snakes[0].health = 101;
+        invalid.board.snakes[0].length = 3;
+        let errors = invalid.validate().unwrap_err();
+        assert_eq!(errors, vec![
+            wire_rep::ValidationError::InvalidHealth{snake: "a".to_string(), health: 101},
+            wire_rep::ValidationError::LengthMismatch{snake: "a".to_string(), length: 3, body: 2},
+        ]);
+        assert_eq!(errors[0].to_string(), "health 101 of snake a is not between 0 and 100");
+        let mut invalid = state(body, "a", "");
+        invalid.board.snakes[0].health = -1;
+        invalid.board.snakes[0].body = vec![wire_rep::Coord{x: 1, y: 1}; 300];
+        invalid.board.snakes[0].length = 300;
+        assert_eq!(invalid.validate(), Err(vec![
+            wire_rep::ValidationError::InvalidHealth{snake: "a".to_string(), health: -1},
+            wire_rep::ValidationError::TooLong{snake: "a".to_string(), length: 300},
+        ]));
+    }
+
+    #[test]
+    fn test_latency() {
+        let snake = |latency: &str| serde_json::from_str::<wire_rep::Battlesnake>(&format!(
+            r#"{{"id":"a","name":"","health":90,"body":[{{"x":1,"y":1}}],"head":{{"x":1,"y":1}},"length":1{}}}"#,
+            latency,
+        ));
+        assert_eq!(snake("").unwrap().latency, None);
+        assert_eq!(snake(r#","latency":"""#).unwrap().latency, None);
+        assert_eq!(snake(r#","latency":"123""#).unwrap().latency, Some(123));
+        assert_eq!(snake(r#","latency":45"#).unwrap().latency, Some(45));
+        assert!(snake(r#","latency":"fast""#).is_err());
+    }
+
+    #[test]
+    fn test_rules_from_gamestate() {
+        assert_eq!(Bitboard::<4, mode::StandardWrapped>::new(mode::StandardWrapped{}).rules, Rules::Standard);
+        assert_eq!(create_board().rules, Rules::Standard);
+        for (ruleset, map, rules) in [
+            ("wrapped-constrictor", "standard", Rules::Constrictor),
+            ("wrapped", "hz_spiral", Rules::Spiral{center: 10*11 + 5}),
+        ] {
+            let mut state = create_board().to_gamestate();
+            state.board.hazards.insert(0, wire_rep::Coord{x: 5, y: 10});
+            state.game.ruleset.name = serde_json::from_value(ruleset.into()).unwrap();
+            state.game.map = map.to_string();
+            let board = Bitboard::<4, mode::StandardWrapped>::from_gamestate(state);
+            assert_eq!(board.rules, rules);
+            let copy = Bitboard::<4, mode::StandardWrapped>::from_str(&board.to_string().unwrap()).unwrap();
+            assert_eq!(copy.rules, rules);
+            assert_eq!(copy.gamemode, board.gamemode);
+        }
     }
     
+    #[test]
+    fn test_dynamic_mode_matches_standard() {
+        let dynamic = mode::Dynamic::with_dimensions(11, 11, true);
+        let standard = mode::StandardWrapped{};
+        assert_eq!(dynamic.n(), standard.n());
+        assert_eq!(dynamic.full_board_mask().count_ones(), standard.full_board_mask().count_ones());
+        assert_eq!(dynamic.checker_board_mask().count_ones(), standard.checker_board_mask().count_ones());
+        for pos in 0..standard.n() as u16 {
+            assert_eq!(dynamic.moves_from_position(pos), standard.moves_from_position(pos));
+        }
+    }
+
+    #[test]
+    fn test_dynamic_bitboard_serde() {
+        let val = r###"{"game":{"id":"","ruleset":{"name":"standard","version":"?","settings":{"foodSpawnChance":15,"minimumFood":1,"hazardDamagePerTurn":14}},"map":"standard","timeout":500,"source":"custom"},"turn":12,"board":{"width":7,"height":9,"food":[{"x":6,"y":8},{"x":0,"y":3}],"hazards":[],"snakes":[{"id":"a","name":"a","health":90,"body":[{"x":1,"y":1},{"x":1,"y":2},{"x":2,"y":2},{"x":3,"y":2}],"head":{"x":1,"y":1},"length":4},{"id":"b","name":"b","health":80,"body":[{"x":5,"y":7},{"x":5,"y":6},{"x":4,"y":6}],"head":{"x":5,"y":7},"length":3}]},"you":{"id":"a","name":"a","health":90,"body":[{"x":1,"y":1},{"x":1,"y":2},{"x":2,"y":2},{"x":3,"y":2}],"head":{"x":1,"y":1},"length":4}}"###;
+        let board = Bitboard::<2, mode::Dynamic>::from_str(&val).unwrap();
+        assert_eq!(board.mode.w(), 7);
+        assert_eq!(board.mode.h(), 9);
+        assert_eq!(board.mode.moves_from_position(6 + 8*7), [None, Some(6 + 7*7), None, Some(5 + 8*7)]);
+        let copy = Bitboard::<2, mode::Dynamic>::from_str(&board.to_string().unwrap()).unwrap();
+        assert_eq!(board.food, copy.food);
+        assert_eq!(board.snakes, copy.snakes);
+        assert_eq!(board.bodies, copy.bodies);
+        assert_eq!(board.turn, copy.turn);
+    }
+
+    #[test]
+    fn test_hazard_stacking() {
+        let val = r###"{"game":{"id":"","ruleset":{"name":"standard","version":"?","settings":{"hazardDamagePerTurn":14}},"map":"standard","timeout":500,"source":""},"turn":10,"board":{"width":11,"height":11,"food":[],"hazards":[{"x":2,"y":1},{"x":1,"y":0},{"x":2,"y":1},{"x":0,"y":1},{"x":1,"y":0},{"x":2,"y":1}],"snakes":[{"id":"a","name":"","health":40,"body":[{"x":1,"y":1},{"x":1,"y":2},{"x":1,"y":3}],"head":{"x":1,"y":1},"length":3,"shout":"","squad":""},{"id":"b","name":"","health":90,"body":[{"x":9,"y":9},{"x":9,"y":8},{"x":9,"y":7}],"head":{"x":9,"y":9},"length":3,"shout":"","squad":""}]},"you":{"id":"a","name":"","health":40,"body":[{"x":1,"y":1},{"x":1,"y":2},{"x":1,"y":3}],"head":{"x":1,"y":1},"length":3,"shout":"","squad":""}}"###;
+        let board = Bitboard::<2, mode::Standard>::from_str(val).unwrap();
+        assert_eq!(board.hazard_stack(2 + 11), 3);
+        assert_eq!(board.hazard_stack(1), 2);
+        assert_eq!(board.hazard_stack(11), 1);
+        assert_eq!(board.hazard_stack(12), 0);
+        assert_eq!(board.hazard_damage(1), 28);
+        assert_eq!(board.hazards_dealing(40), <mode::Standard as Mode>::Bitset::with_bit_set(2 + 11));
+        assert_eq!(board.hazards_dealing(20).count_ones(), 2);
+        assert_eq!(board.hazards_dealing(1), board.hazard_mask);
+
+        // the triple stack deals more damage than we have health left
+        let moves = move_gen::allowed_moves(&board, 0);
+        assert!(!moves.contains(&Move::Right));
+        assert!(moves.contains(&Move::Left) && moves.contains(&Move::Down));
+
+        let mut child = board.clone();
+        child.play_moves(&[Move::Down, Move::Up]);
+        assert_eq!(child.snakes[0].health, 40 - 1 - 28);
+        let mut child = board.clone();
+        child.play_moves(&[Move::Right, Move::Up]);
+        assert!(child.snakes[0].is_dead());
+
+        let copy = Bitboard::<2, mode::Standard>::from_str(&board.to_string().unwrap()).unwrap();
+        assert_eq!(copy.to_gamestate().board.hazards.len(), 6);
+        assert_eq!(copy.hazard_mask, board.hazard_mask);
+        assert_eq!(copy.stacked_hazards, board.stacked_hazards);
+        assert_eq!(copy.zobrist_key, board.zobrist_key);
+    }
+
+    #[test]
+    fn test_royale_shrinking() {
+        let val = r###"{"game":{"id":"","ruleset":{"name":"standard","version":"?","settings":{"hazardDamagePerTurn":14,"royale":{"shrinkEveryNTurns":25}}},"map":"royale","timeout":500,"source":""},"turn":24,"board":{"width":11,"height":11,"food":[],"hazards":[{"x":0,"y":10},{"x":1,"y":10},{"x":2,"y":10},{"x":3,"y":10},{"x":4,"y":10},{"x":5,"y":10},{"x":6,"y":10},{"x":7,"y":10},{"x":8,"y":10},{"x":9,"y":10},{"x":10,"y":10}],"snakes":[{"id":"a","name":"","health":90,"body":[{"x":2,"y":4},{"x":2,"y":5},{"x":2,"y":6}],"head":{"x":2,"y":4},"length":3,"shout":"","squad":""},{"id":"b","name":"","health":90,"body":[{"x":8,"y":8},{"x":8,"y":7},{"x":8,"y":6}],"head":{"x":8,"y":8},"length":3,"shout":"","squad":""}]},"you":{"id":"a","name":"","health":90,"body":[{"x":2,"y":4},{"x":2,"y":5},{"x":2,"y":6}],"head":{"x":2,"y":4},"length":3,"shout":"","squad":""}}"###;
+        let board = Bitboard::<2, mode::Standard>::from_str(val).unwrap();
+        assert_eq!(board.rules, Rules::Royale{shrink_every: 25});
+        let copy = Bitboard::<2, mode::Standard>::from_str(&board.to_string().unwrap()).unwrap();
+        assert_eq!(copy.rules, board.rules);
+
+        // the left edge is closest to our head after moving left
+        let mut child = board.clone();
+        child.play_moves(&[Move::Left, Move::Up]);
+        assert_eq!(child.hazard_mask.count_ones(), 11 + 10);
+        for y in 0..11 {
+            assert!(child.hazard_mask.get(11*y));
+        }
+        assert_eq!(child.zobrist_key, child.compute_zobrist_key());
+
+        // nothing happens in between shrinks
+        child.play_moves(&[Move::Down, Move::Up]);
+        assert_eq!(child.hazard_mask.count_ones(), 11 + 10);
+    }
+
+    #[test]
+    fn test_sinkholes() {
+        let mut state = create_board().to_gamestate();
+        state.game.map = "sinkholes".to_string();
+        state.board.hazards = vec![wire_rep::Coord{x: 5, y: 5}];
+        state.turn = 11;
+        let board = Bitboard::<4, mode::StandardWrapped>::from_gamestate(state);
+        assert_eq!(board.gamemode, Gamemode::WrappedSinkholes);
+        assert_eq!(board.rules, Rules::Sinkholes{spawn_every: 10, max_rings: 5});
+        let copy = Bitboard::<4, mode::StandardWrapped>::from_str(&board.to_string().unwrap()).unwrap();
+        assert_eq!(copy.gamemode, board.gamemode);
+        assert_eq!(copy.rules, board.rules);
+
+        // the first ring is added after turn 11 and stacks on the center
+        let mut child = board.clone();
+        child.play_moves(&move_gen::random_move_combination(&board, &mut Pcg64Mcg::new(1)));
+        assert_eq!(child.hazard_mask.count_ones(), 5);
+        assert_eq!(child.hazard_stack(5*11 + 5), 2);
+        assert_eq!(child.hazard_stack(5*11 + 6), 1);
+        assert_eq!(child.hazard_stack(6*11 + 6), 0);
+        assert_eq!(child.zobrist_key, child.compute_zobrist_key());
+    }
+
+    #[test]
+    fn test_snail_trails() {
+        let val = r###"{"game":{"id":"","ruleset":{"name":"standard","version":"?","settings":{"hazardDamagePerTurn":14}},"map":"snail_mode","timeout":500,"source":""},"turn":10,"board":{"width":11,"height":11,"food":[],"hazards":[],"snakes":[{"id":"a","name":"","health":90,"body":[{"x":1,"y":1},{"x":1,"y":2},{"x":1,"y":3}],"head":{"x":1,"y":1},"length":3,"shout":"","squad":""},{"id":"b","name":"","health":90,"body":[{"x":8,"y":8},{"x":8,"y":7},{"x":8,"y":6},{"x":8,"y":6}],"head":{"x":8,"y":8},"length":4,"shout":"","squad":""}]},"you":{"id":"a","name":"","health":90,"body":[{"x":1,"y":1},{"x":1,"y":2},{"x":1,"y":3}],"head":{"x":1,"y":1},"length":3,"shout":"","squad":""}}"###;
+        let mut board = Bitboard::<2, mode::Standard>::from_str(val).unwrap();
+        assert_eq!(board.rules, Rules::SnailMode);
+
+        // b's tail is stacked, so only our tail leaves a trail
+        board.play_moves(&[Move::Down, Move::Up]);
+        assert_eq!(board.hazard_stack(3*11 + 1), SNAIL_TRAIL_LENGTH as u8);
+        assert_eq!(board.hazard_mask.count_ones(), 1);
+
+        board.play_moves(&[Move::Right, Move::Up]);
+        assert_eq!(board.hazard_stack(3*11 + 1), SNAIL_TRAIL_LENGTH as u8 - 1);
+        assert_eq!(board.hazard_stack(2*11 + 1), SNAIL_TRAIL_LENGTH as u8);
+        assert_eq!(board.hazard_stack(6*11 + 8), SNAIL_TRAIL_LENGTH as u8);
+        assert_eq!(board.zobrist_key, board.compute_zobrist_key());
+
+        // fresh trails are deadly
+        let mut child = board.clone();
+        child.play_moves(&[Move::Up, Move::Up]);
+        child.play_moves(&[Move::Left, Move::Up]);
+        assert!(child.snakes[0].is_dead());
+    }
+
+    #[test]
+    fn test_healing_pools() {
+        let val = r###"{"game":{"id":"","ruleset":{"name":"standard","version":"?","settings":{"hazardDamagePerTurn":-20,"royale":{"shrinkEveryNTurns":10}}},"map":"healing_pools","timeout":500,"source":""},"turn":10,"board":{"width":11,"height":11,"food":[],"hazards":[{"x":1,"y":0},{"x":5,"y":5},{"x":9,"y":9}],"snakes":[{"id":"a","name":"","health":50,"body":[{"x":1,"y":1},{"x":1,"y":2},{"x":1,"y":3}],"head":{"x":1,"y":1},"length":3,"shout":"","squad":""},{"id":"b","name":"","health":90,"body":[{"x":8,"y":8},{"x":8,"y":7},{"x":8,"y":6}],"head":{"x":8,"y":8},"length":3,"shout":"","squad":""}]},"you":{"id":"a","name":"","health":50,"body":[{"x":1,"y":1},{"x":1,"y":2},{"x":1,"y":3}],"head":{"x":1,"y":1},"length":3,"shout":"","squad":""}}"###;
+        let mut board = Bitboard::<2, mode::Standard>::from_str(val).unwrap();
+        assert_eq!(board.gamemode, Gamemode::StandardHealingPools);
+        assert_eq!(board.rules, Rules::HealingPools{shrink_every: 10});
+        let copy = Bitboard::<2, mode::Standard>::from_str(&board.to_string().unwrap()).unwrap();
+        assert_eq!(copy.gamemode, board.gamemode);
+        assert_eq!(copy.rules, board.rules);
+
+        // we heal on the pool and use it up, then the pool closest to us dries up
+        board.play_moves(&[Move::Down, Move::Left]);
+        assert_eq!(board.snakes[0].health, 50 - 1 + 20);
+        assert_eq!(board.snakes[1].health, 89);
+        assert!(!board.hazard_mask.get(1));
+        assert!(!board.hazard_mask.get(5*11 + 5));
+        assert!(board.hazard_mask.get(9*11 + 9));
+        assert_eq!(board.zobrist_key, board.compute_zobrist_key());
+    }
+
+    #[test]
+    fn test_squads() {
+        let val = r###"{"game":{"id":"","ruleset":{"name":"squad","version":"?","settings":{"squad":{"allowBodyCollisions":false,"sharedElimination":true,"sharedHealth":true,"sharedLength":true}}},"map":"standard","timeout":500,"source":""},"turn":10,"board":{"width":11,"height":11,"food":[{"x":6,"y":1}],"hazards":[],"snakes":[{"id":"a","name":"","health":50,"body":[{"x":1,"y":1},{"x":1,"y":2},{"x":1,"y":3}],"head":{"x":1,"y":1},"length":3,"shout":"","squad":"x"},{"id":"b","name":"","health":90,"body":[{"x":9,"y":9},{"x":9,"y":8},{"x":9,"y":7}],"head":{"x":9,"y":9},"length":3,"shout":"","squad":"y"},{"id":"c","name":"","health":80,"body":[{"x":5,"y":1},{"x":5,"y":2},{"x":5,"y":3}],"head":{"x":5,"y":1},"length":3,"shout":"","squad":"x"},{"id":"d","name":"","health":70,"body":[{"x":9,"y":1},{"x":9,"y":2},{"x":9,"y":3}],"head":{"x":9,"y":1},"length":3,"shout":"","squad":"y"}]},"you":{"id":"a","name":"","health":50,"body":[{"x":1,"y":1},{"x":1,"y":2},{"x":1,"y":3}],"head":{"x":1,"y":1},"length":3,"shout":"","squad":"x"}}"###;
+        let mut board = Bitboard::<4, mode::Standard>::from_str(val).unwrap();
+        assert_eq!(board.gamemode, Gamemode::Squad);
+        assert_eq!(board.rules, Rules::Squad);
+        assert_eq!(board.squads, [0, 1, 0, 1]);
+        assert!(board.is_ally(0, 2) && board.is_ally(1, 3));
+        assert!(!board.is_ally(0, 0) && !board.is_ally(0, 1));
+        let copy = Bitboard::<4, mode::Standard>::from_str(&board.to_string().unwrap()).unwrap();
+        assert_eq!(copy.squads, board.squads);
+        assert_eq!(copy.rules, board.rules);
+
+        // our ally doesn't branch
+        let history = vec![[0; 4]; board.mode.n()];
+        let mvs = move_gen::ordered_limited_move_combinations(&board, 1, &history);
+        assert!(mvs.len() > 1);
+        assert!(mvs.iter().all(|mv| mv[2] == mvs[0][2]));
+
+        // our ally eats, we share its health and length
+        board.play_moves(&[Move::Right, Move::Up, Move::Right, Move::Down]);
+        assert_eq!((board.snakes[0].health, board.snakes[0].length, board.snakes[0].curled_bodyparts), (100, 4, 1));
+        assert_eq!((board.snakes[2].health, board.snakes[2].length), (100, 4));
+        assert_eq!(board.snakes[1].health, 89);
+        assert_eq!(board.snakes[3].health, 89);
+        assert!(board.bodies[0].get(board.snakes[0].tail as usize));
+        assert_eq!(board.zobrist_key, board.compute_zobrist_key());
+
+        // an enemy moves out of bounds and takes its ally down with it
+        board.play_moves(&[Move::Right, Move::Up, Move::Right, Move::Left]);
+        assert!(board.snakes[1].is_dead() && board.snakes[3].is_dead());
+        assert!(board.is_terminal());
+        assert_eq!(board.win_draw_loss(), Some(1));
+        assert_eq!(board.zobrist_key, board.compute_zobrist_key());
+
+        // we die, but our ally plays on
+        let mut board = Bitboard::<4, mode::Standard>::from_str(val).unwrap();
+        board.settings.squad = wire_rep::SquadSettings{
+            allow_body_collisions: false,
+            shared_elimination: false,
+            shared_health: false,
+            shared_length: false,
+        };
+        board.play_moves(&[Move::Up, Move::Up, Move::Right, Move::Down]);
+        assert!(board.snakes[0].is_dead() && board.snakes[2].is_alive());
+        assert!(board.is_terminal());
+        assert_eq!(board.win_draw_loss(), Some(0));
+    }
+
+    #[test]
+    fn test_squad_body_collisions() {
+        let val = r###"{"game":{"id":"","ruleset":{"name":"squad","version":"?","settings":{"squad":{"allowBodyCollisions":true,"sharedElimination":false,"sharedHealth":false,"sharedLength":false}}},"map":"standard","timeout":500,"source":""},"turn":10,"board":{"width":11,"height":11,"food":[],"hazards":[],"snakes":[{"id":"a","name":"","health":50,"body":[{"x":1,"y":1},{"x":1,"y":2},{"x":1,"y":3}],"head":{"x":1,"y":1},"length":3,"shout":"","squad":"x"},{"id":"b","name":"","health":90,"body":[{"x":9,"y":9},{"x":9,"y":8},{"x":9,"y":7}],"head":{"x":9,"y":9},"length":3,"shout":"","squad":"y"},{"id":"c","name":"","health":80,"body":[{"x":3,"y":2},{"x":3,"y":1},{"x":2,"y":1},{"x":2,"y":0},{"x":3,"y":0}],"head":{"x":3,"y":2},"length":5,"shout":"","squad":"x"},{"id":"d","name":"","health":70,"body":[{"x":9,"y":1},{"x":9,"y":2},{"x":9,"y":3}],"head":{"x":9,"y":1},"length":3,"shout":"","squad":"y"}]},"you":{"id":"a","name":"","health":50,"body":[{"x":1,"y":1},{"x":1,"y":2},{"x":1,"y":3}],"head":{"x":1,"y":1},"length":3,"shout":"","squad":"x"}}"###;
+        let body = |board: &Bitboard<4, mode::Standard>, i: usize| -> Vec<(i32, i32)> {
+            board.to_gamestate().board.snakes[i].body.iter().map(|p| (p.x, p.y)).collect()
+        };
+
+        // we move our head onto our ally's body and survive
+        let mut board = Bitboard::<4, mode::Standard>::from_str(val).unwrap();
+        let start = board.clone();
+        let undo = board.make_moves(&[Move::Right, Move::Left, Move::Up, Move::Right]);
+        assert!(board.snakes.iter().all(|snake| snake.is_alive()));
+        assert_eq!(board.crossings.len(), 1);
+        assert_eq!(body(&board, 0), vec![(2, 1), (1, 1), (1, 2)]);
+        assert_eq!(body(&board, 2), vec![(3, 3), (3, 2), (3, 1), (2, 1), (2, 0)]);
+        assert_eq!(board.zobrist_key, board.compute_zobrist_key());
+        let copy = Bitboard::<4, mode::Standard>::from_gamestate(board.to_gamestate());
+        assert_eq!((body(&copy, 0), body(&copy, 2)), (body(&board, 0), body(&board, 2)));
+        assert_eq!(copy.crossings.len(), 1);
+        board.unmake_moves(undo);
+        assert!(board == start);
+
+        // we move on through our ally, until its tail has left the shared cell
+        board.play_moves(&[Move::Right, Move::Left, Move::Up, Move::Right]);
+        board.play_moves(&[Move::Up, Move::Left, Move::Up, Move::Up]);
+        assert_eq!(body(&board, 0), vec![(2, 2), (2, 1), (1, 1)]);
+        assert_eq!(body(&board, 2), vec![(3, 4), (3, 3), (3, 2), (3, 1), (2, 1)]);
+        board.play_moves(&[Move::Up, Move::Left, Move::Up, Move::Up]);
+        assert!(board.snakes.iter().all(|snake| snake.is_alive()));
+        assert!(board.crossings.is_empty());
+        assert_eq!(body(&board, 0), vec![(2, 3), (2, 2), (2, 1)]);
+        assert_eq!(body(&board, 2), vec![(3, 5), (3, 4), (3, 3), (3, 2), (3, 1)]);
+        assert_eq!(board.zobrist_key, board.compute_zobrist_key());
+
+        // without the setting, allies collide like enemies
+        let mut board = Bitboard::<4, mode::Standard>::from_str(val).unwrap();
+        board.settings.squad.allow_body_collisions = false;
+        board.play_moves(&[Move::Right, Move::Left, Move::Up, Move::Right]);
+        assert!(board.snakes[0].is_dead() && board.snakes[2].is_alive());
+    }
+
+    #[test]
+    fn test_squad_shared_cells() {
+        let val = r###"{"game":{"id":"","ruleset":{"name":"squad","version":"?","settings":{"squad":{"allowBodyCollisions":true,"sharedElimination":false,"sharedHealth":false,"sharedLength":false}}},"map":"standard","timeout":500,"source":""},"turn":10,"board":{"width":11,"height":11,"food":[],"hazards":[],"snakes":[{"id":"a","name":"","health":90,"body":[{"x":2,"y":2},{"x":1,"y":2},{"x":0,"y":2}],"head":{"x":2,"y":2},"length":3,"shout":"","squad":"x"},{"id":"c","name":"","health":90,"body":[{"x":3,"y":5},{"x":3,"y":4},{"x":3,"y":3},{"x":3,"y":2},{"x":3,"y":1},{"x":3,"y":0}],"head":{"x":3,"y":5},"length":6,"shout":"","squad":"x"},{"id":"e","name":"","health":90,"body":[{"x":5,"y":2},{"x":6,"y":2},{"x":7,"y":2}],"head":{"x":5,"y":2},"length":3,"shout":"","squad":"x"},{"id":"d","name":"","health":90,"body":[{"x":5,"y":1},{"x":6,"y":1},{"x":7,"y":1}],"head":{"x":5,"y":1},"length":3,"shout":"","squad":"y"}]},"you":{"id":"a","name":"","health":90,"body":[{"x":2,"y":2},{"x":1,"y":2},{"x":0,"y":2}],"head":{"x":2,"y":2},"length":3,"shout":"","squad":"x"}}"###;
+        let body = |board: &Bitboard<4, mode::Standard>, i: usize| -> Vec<(i32, i32)> {
+            board.to_gamestate().board.snakes[i].body.iter().map(|p| (p.x, p.y)).collect()
+        };
+        let mut board = Bitboard::<4, mode::Standard>::from_str(val).unwrap();
+        let start = board.clone();
+        let mut undos = vec![];
+
+        // two allies cross the same cell, which is where our ally's tail ends up
+        undos.push(board.make_moves(&[Move::Right, Move::Up, Move::Left, Move::Left]));
+        undos.push(board.make_moves(&[Move::Up, Move::Up, Move::Left, Move::Left]));
+        let shared = 2*11 + 3;
+        assert!(board.snakes.iter().all(|snake| snake.is_alive()));
+        assert_eq!(board.snakes[1].tail, shared);
+        assert!(board.bodies[0].get(shared as usize));
+        assert_eq!(board.crossings.iter().filter(|c| c.pos == shared).count(), 2);
+
+        // the shared cell stays occupied for our enemy, and the allies untangle
+        undos.push(board.make_moves(&[Move::Up, Move::Up, Move::Up, Move::Up]));
+        assert!(board.snakes[3].is_dead());
+        assert!(board.snakes[..3].iter().all(|snake| snake.is_alive()));
+        assert_eq!(body(&board, 0), vec![(3, 4), (3, 3), (3, 2)]);
+        assert_eq!(body(&board, 1), vec![(3, 8), (3, 7), (3, 6), (3, 5), (3, 4), (3, 3)]);
+        assert_eq!(body(&board, 2), vec![(3, 3), (3, 2), (4, 2)]);
+        assert_eq!(board.zobrist_key, board.compute_zobrist_key());
+        let copy = Bitboard::<4, mode::Standard>::from_gamestate(board.to_gamestate());
+        assert_eq!((body(&copy, 0), body(&copy, 1), body(&copy, 2)), (body(&board, 0), body(&board, 1), body(&board, 2)));
+
+        while let Some(undo) = undos.pop() {
+            board.unmake_moves(undo);
+        }
+        assert!(board == start);
+    }
+
+    #[test]
+    fn test_solo() {
+        let val = r###"{"game":{"id":"","ruleset":{"name":"solo","version":"?","settings":{}},"map":"standard","timeout":500,"source":""},"turn":10,"board":{"width":11,"height":11,"food":[],"hazards":[],"snakes":[{"id":"a","name":"","health":2,"body":[{"x":1,"y":1},{"x":1,"y":2},{"x":1,"y":3}],"head":{"x":1,"y":1},"length":3,"shout":"","squad":""}]},"you":{"id":"a","name":"","health":2,"body":[{"x":1,"y":1},{"x":1,"y":2},{"x":1,"y":3}],"head":{"x":1,"y":1},"length":3,"shout":"","squad":""}}"###;
+        let mut board = Bitboard::<1, mode::Standard>::from_str(val).unwrap();
+        assert_eq!(board.gamemode, Gamemode::Solo);
+        let copy = Bitboard::<1, mode::Standard>::from_str(&board.to_string().unwrap()).unwrap();
+        assert_eq!(copy.gamemode, board.gamemode);
+
+        // being alone on the board is no win, the game goes on until we starve
+        assert!(!board.is_terminal());
+        assert_eq!(board.win_draw_loss(), None);
+        board.play_moves(&[Move::Right]);
+        assert!(!board.is_terminal());
+        board.play_moves(&[Move::Right]);
+        assert!(board.is_terminal());
+        assert_eq!(board.win_draw_loss(), Some(-1));
+    }
+
+    #[test]
+    fn test_spawn_food() {
+        let mut rng = Pcg64Mcg::new(91825765198273048172569872943871926276_u128);
+        let mut state = create_board().to_gamestate();
+        state.board.food.clear();
+        state.game.ruleset.settings.minimum_food = 3;
+        state.game.ruleset.settings.food_spawn_chance = 0;
+        let board = Bitboard::<4, mode::StandardWrapped>::from_gamestate(state);
+        assert_eq!((board.settings.minimum_food, board.settings.food_spawn_chance), (3, 0));
+
+        // food is topped up to the minimum, but never spawns on snakes or hazards
+        for _ in 0..20 {
+            let mut child = board.clone();
+            child.spawn_food(&mut rng);
+            assert_eq!(child.food.count_ones(), 3);
+            assert!(!(child.food & (child.bodies[0] | child.hazard_mask)).any());
+            assert!(child.snakes.iter().all(|snake| !child.food.get(snake.tail as usize)));
+            assert_eq!(child.zobrist_key, child.compute_zobrist_key());
+            child.spawn_food(&mut rng);
+            assert_eq!(child.food.count_ones(), 3);
+        }
+
+        // spawned food is taken back with the joint move
+        let mut child = board.clone();
+        let moves = move_gen::random_move_combination(&child, &mut rng);
+        let undo = child.make_moves(&moves);
+        let key = child.zobrist_key;
+        child.spawn_food(&mut rng);
+        assert_ne!(child.zobrist_key, key);
+        child.unmake_moves(undo);
+        assert_eq!(child.food, board.food);
+        assert_eq!(child.zobrist_key, board.zobrist_key);
+    }
+
+    #[test]
+    fn test_spawn_food_chance() {
+        let mut rng = Pcg64Mcg::new(91825765198273048172569872943871926276_u128);
+        let mut board = create_board();
+        board.settings.food_spawn_chance = 100;
+        let food_count = board.food.count_ones();
+        board.spawn_food(&mut rng);
+        assert_eq!(board.food.count_ones(), food_count + 1);
+        board.settings.food_spawn_chance = 0;
+        board.spawn_food(&mut rng);
+        assert_eq!(board.food.count_ones(), food_count + 1);
+    }
+
+    #[test]
+    fn test_incremental_zobrist_key() {
+        let mut rng = Pcg64Mcg::new(91825765198273048172569872943871926276_u128);
+        for (ruleset, map) in [("wrapped", "hz_islands_bridges"), ("wrapped", "hz_spiral"), ("wrapped-constrictor", "standard")] {
+            let mut state = create_board().to_gamestate();
+            state.game.ruleset.name = serde_json::from_value(ruleset.into()).unwrap();
+            state.game.map = map.to_string();
+            let start = Bitboard::<4, mode::StandardWrapped>::from_gamestate(state);
+            assert_eq!(start.zobrist_key, start.compute_zobrist_key());
+            for _ in 0..20 {
+                let mut board = start.clone();
+                while !board.is_terminal() {
+                    let moves = move_gen::random_move_combination(&board, &mut rng);
+                    board.play_moves(&moves);
+                    assert_eq!(board.zobrist_key, board.compute_zobrist_key());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_make_unmake_moves() {
+        let standard = r###"{"game":{"id":"","ruleset":{"name":"standard","version":"?","settings":{"hazardDamagePerTurn":14}},"map":"standard","timeout":500,"source":""},"turn":20,"board":{"width":11,"height":11,"food":[{"x":5,"y":5},{"x":0,"y":10},{"x":3,"y":4}],"hazards":[{"x":0,"y":0},{"x":1,"y":0},{"x":2,"y":0},{"x":0,"y":1},{"x":10,"y":10},{"x":5,"y":6}],"snakes":[{"id":"a","name":"","health":80,"body":[{"x":1,"y":2},{"x":1,"y":3},{"x":1,"y":4},{"x":2,"y":4},{"x":2,"y":5}],"head":{"x":1,"y":2},"length":5,"shout":"","squad":""},{"id":"b","name":"","health":12,"body":[{"x":8,"y":8},{"x":8,"y":7},{"x":8,"y":6},{"x":8,"y":6}],"head":{"x":8,"y":8},"length":4,"shout":"","squad":""},{"id":"c","name":"","health":50,"body":[{"x":4,"y":8},{"x":5,"y":8},{"x":6,"y":8}],"head":{"x":4,"y":8},"length":3,"shout":"","squad":""},{"id":"d","name":"","health":90,"body":[{"x":6,"y":2},{"x":6,"y":1},{"x":7,"y":1},{"x":8,"y":1},{"x":8,"y":2},{"x":8,"y":3}],"head":{"x":6,"y":2},"length":6,"shout":"","squad":""}]},"you":{"id":"a","name":"","health":80,"body":[{"x":1,"y":2},{"x":1,"y":3},{"x":1,"y":4},{"x":2,"y":4},{"x":2,"y":5}],"head":{"x":1,"y":2},"length":5,"shout":"","squad":""}}"###;
+        let wrapped = create_board().to_string().unwrap();
+        let cases = [
+            (Gamemode::Standard, "standard", "standard"),
+            (Gamemode::StandardWithHazard, "standard", "royale"),
+            (Gamemode::StandardSnailMode, "standard", "snail_mode"),
+            (Gamemode::StandardHealingPools, "standard", "healing_pools"),
+            (Gamemode::StandardRiversAndBridges, "standard", "rivers_and_bridges"),
+            (Gamemode::StandardColumns, "standard", "hz_columns"),
+            (Gamemode::StandardRings, "standard", "hz_rings"),
+            (Gamemode::StandardScatterFill, "standard", "hz_scatter"),
+            (Gamemode::Constrictor, "constrictor", "standard"),
+            (Gamemode::WrappedConstrictor, "wrapped-constrictor", "standard"),
+            (Gamemode::Squad, "squad", "standard"),
+            (Gamemode::Wrapped, "wrapped", "standard"),
+            (Gamemode::WrappedWithHazard, "wrapped", "royale"),
+            (Gamemode::WrappedSpiral, "wrapped", "hz_spiral"),
+            (Gamemode::WrappedArcadeMaze, "wrapped", "arcade_maze"),
+            (Gamemode::WrappedSinkholes, "wrapped", "sinkholes"),
+            (Gamemode::WrappedIslandsBridges, "wrapped", "hz_islands_bridges"),
+            (Gamemode::Solo, "solo", "standard"),
+        ];
+        let mut rng = Pcg64Mcg::new(91825765198273048172569872943871926276_u128);
+        for (gamemode, ruleset, map) in cases {
+            let mut state = serde_json::from_str::<wire_rep::GameState>(if ruleset.contains("wrapped") { &wrapped } else { standard }).unwrap();
+            state.game.ruleset.name = serde_json::from_value(ruleset.into()).unwrap();
+            state.game.map = map.to_string();
+            if gamemode == Gamemode::Standard || gamemode == Gamemode::Wrapped {
+                state.board.hazards.clear();
+            }
+            if gamemode == Gamemode::Squad {
+                for (i, snake) in state.board.snakes.iter_mut().enumerate() {
+                    snake.squad = Some((i / 2).to_string());
+                }
+            }
+            if gamemode == Gamemode::Solo {
+                state.board.snakes.truncate(1);
+                let start = Bitboard::<1, mode::Dynamic>::from_gamestate(state);
+                assert_eq!(start.gamemode, gamemode);
+                make_and_unmake_games(&start, &mut rng);
+            } else {
+                let start = Bitboard::<4, mode::Dynamic>::from_gamestate(state);
+                assert_eq!(start.gamemode, gamemode);
+                make_and_unmake_games(&start, &mut rng);
+            }
+        }
+    }
+
+    fn make_and_unmake_games<const S: usize>(start: &Bitboard<S, mode::Dynamic>, rng: &mut Pcg64Mcg) {
+        let assert_same = |a: &Bitboard<S, mode::Dynamic>, b: &Bitboard<S, mode::Dynamic>| {
+            assert!(a == b, "{:?}\n{:?}\n{:?}", start.gamemode, a, b);
+        };
+        for _ in 0..20 {
+            // make moves until the game ends, then take them all back
+            let mut board = start.clone();
+            let mut history = vec![];
+            let mut undos = vec![];
+            while !board.is_terminal() {
+                let moves = move_gen::random_move_combination(&board, rng);
+                history.push(board.clone());
+                undos.push(board.make_moves(&moves));
+            }
+            assert!(board.clone().undo_log.is_empty());
+            while let Some(undo) = undos.pop() {
+                board.unmake_moves(undo);
+                assert_same(&board, &history.pop().unwrap());
+            }
+            assert_same(&board, start);
+            assert!(board.undo_log.is_empty());
+        }
+    }
+
     #[bench]
     fn bench_simulate(b: &mut Bencher) {
         let mut board = create_board();
         b.iter(|| {
             let moves = move_gen::limited_move_combinations(&board, 0);
-            (board.apply_moves.clone())(&mut board, &moves[0])
+            board.play_moves(&moves[0])
         })
     }
 
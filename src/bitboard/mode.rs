@@ -1,92 +1,236 @@
-use crate::bitboard::{bitset::BitsetTrait, constants};
+use std::sync::Arc;
 
-pub trait Mode: Sized + Clone + Send + 'static {
-    const W: usize;
-    const H: usize;
-    const WRAP: bool;
+use crate::bitboard::{bitset::BitsetTrait, constants, is_wrapped};
+use crate::wire_rep;
 
-    const N: usize;
-    const L: usize;
+/// Describes the geometry of a board.
+/// Modes with dimensions that are known at compile time are zero sized and all of their
+/// accessors compile down to constants, the `Dynamic` mode carries its layout at runtime.
+pub trait Mode: Sized + Clone + Send + Sync + 'static {
     type Bitset: BitsetTrait;
 
-    const FULL_BOARD_MASK: Self::Bitset;
-    const CHECKER_BOARD_MASK: Self::Bitset;
-    const ALL_BUT_LEFT_EDGE_MASK: Self::Bitset;
-    const ALL_BUT_RIGHT_EDGE_MASK: Self::Bitset;
-    const TOP_EDGE_MASK: Self::Bitset;
-    const BOTTOM_EDGE_MASK: Self::Bitset;
-    const LEFT_EDGE_MASK: Self::Bitset;
-    const RIGHT_EDGE_MASK: Self::Bitset;
-
-    // An opaque type, used for moves_from_position.
-    type MoveTable;
-    const MOVE_TABLE: Self::MoveTable;
-    fn moves_from_position(pos: u16) -> [Option<u16>; 4];
-
-    // TODO: generate the implementations of this via a macro
-    // TODO: move the extra stuff in attach_rules here?
-}
+    /// Creates the mode for a board of the given dimensions.
+    /// Panics if the mode can not represent a board of this size.
+    fn with_dimensions(w: usize, h: usize, wrap: bool) -> Self;
+
+    fn w(&self) -> usize;
+    fn h(&self) -> usize;
+    fn wrap(&self) -> bool;
 
-const fn hz_stack_len(w: usize, h: usize, stack: bool) -> usize {
-    if stack {
-        w * h
-    } else {
-        0
+    #[inline(always)]
+    fn n(&self) -> usize {
+        self.w() * self.h()
     }
+
+    fn full_board_mask(&self) -> Self::Bitset;
+    fn checker_board_mask(&self) -> Self::Bitset;
+    fn all_but_left_edge_mask(&self) -> Self::Bitset;
+    fn all_but_right_edge_mask(&self) -> Self::Bitset;
+    fn top_edge_mask(&self) -> Self::Bitset;
+    fn bottom_edge_mask(&self) -> Self::Bitset;
+    fn left_edge_mask(&self) -> Self::Bitset;
+    fn right_edge_mask(&self) -> Self::Bitset;
+
+    fn moves_from_position(&self, pos: u16) -> [Option<u16>; 4];
+
+    // TODO: move the extra stuff in Rules::from_gamestate here?
 }
 
-#[derive(Clone)]
-pub struct Standard{}
-impl Mode for Standard {
-    const W: usize = 11;
-    const H: usize = 11;
-    const WRAP: bool = false;
-
-    const N: usize = Self::W * Self::H;
-    const L: usize = (Self::W*Self::H+63)/64;
-    type Bitset = crate::bitboard::bitset::Bitset<{Self::N}, {Self::L}>;
-        
-    const FULL_BOARD_MASK: Self::Bitset = Self::Bitset::with_all_bits_set();
-    const CHECKER_BOARD_MASK: Self::Bitset = constants::checker_board_mask::<{Self::N}, {Self::L}>(Self::W, Self::H);
-    const ALL_BUT_LEFT_EDGE_MASK: Self::Bitset = constants::border_mask::<{Self::N}, {Self::L}>(Self::W, Self::H, true);
-    const ALL_BUT_RIGHT_EDGE_MASK: Self::Bitset = constants::border_mask::<{Self::N}, {Self::L}>(Self::W, Self::H, false);
-    const TOP_EDGE_MASK: Self::Bitset = constants::horizontal_edge_mask::<{Self::N}, {Self::L}>(Self::W, Self::H, true);
-    const BOTTOM_EDGE_MASK: Self::Bitset = constants::horizontal_edge_mask::<{Self::N}, {Self::L}>(Self::W, Self::H, false);
-    const LEFT_EDGE_MASK: Self::Bitset = constants::vertical_edge_mask::<{Self::N}, {Self::L}>(Self::W, Self::H, true);
-    const RIGHT_EDGE_MASK: Self::Bitset = constants::vertical_edge_mask::<{Self::N}, {Self::L}>(Self::W, Self::H, false);
-
-    type MoveTable = [[Option<u16>; 4]; Self::N];
-    const MOVE_TABLE: Self::MoveTable = constants::precompute_moves::<{Self::N}, {Self::L}>(Self::W, Self::H, Self::WRAP);
-
-    fn moves_from_position(pos: u16) -> [Option<u16>; 4] {
-        Self::MOVE_TABLE[pos as usize]
-    }
+/// Something that can be run on a board of any mode and snake count.
+/// Used with `dispatch` to pick the fastest available mode at runtime.
+pub trait ModeVisitor {
+    type Output;
+    fn visit<const S: usize, MODE: Mode>(self, state: wire_rep::GameState) -> Self::Output;
+}
+
+/// Declares modes with dimensions that are known at compile time, as well as the `dispatch`
+/// function that picks one of them for a gamestate.
+/// Every mode is monomorphized for each of its listed snake counts, a gamestate is dispatched to
+/// the smallest snake count that fits all of its snakes.
+/// Gamestates that don't match any of the declared modes fall back to the runtime sized mode.
+macro_rules! modes {
+    (
+        fallback $fallback:ident: snakes: [$($fs:literal),+ $(,)?];
+        $(
+            $(#[$attr:meta])*
+            $name:ident: $w:literal x $h:literal, wrap: $wrap:literal, snakes: [$($s:literal),+ $(,)?];
+        )*
+    ) => {
+        $(
+            $(#[$attr])*
+            #[derive(Clone, Copy)]
+            pub struct $name{}
+
+            $(#[$attr])*
+            impl $name {
+                const W: usize = $w;
+                const H: usize = $h;
+                const WRAP: bool = $wrap;
+
+                const N: usize = Self::W * Self::H;
+                const L: usize = (Self::W*Self::H).div_ceil(64);
+
+                const FULL_BOARD_MASK: <Self as Mode>::Bitset = <Self as Mode>::Bitset::with_all_bits_set();
+                const CHECKER_BOARD_MASK: <Self as Mode>::Bitset = constants::checker_board_mask::<{Self::N}, {Self::L}>(Self::W, Self::H);
+                const ALL_BUT_LEFT_EDGE_MASK: <Self as Mode>::Bitset = constants::border_mask::<{Self::N}, {Self::L}>(Self::W, Self::H, true);
+                const ALL_BUT_RIGHT_EDGE_MASK: <Self as Mode>::Bitset = constants::border_mask::<{Self::N}, {Self::L}>(Self::W, Self::H, false);
+                const TOP_EDGE_MASK: <Self as Mode>::Bitset = constants::horizontal_edge_mask::<{Self::N}, {Self::L}>(Self::W, Self::H, true);
+                const BOTTOM_EDGE_MASK: <Self as Mode>::Bitset = constants::horizontal_edge_mask::<{Self::N}, {Self::L}>(Self::W, Self::H, false);
+                const LEFT_EDGE_MASK: <Self as Mode>::Bitset = constants::vertical_edge_mask::<{Self::N}, {Self::L}>(Self::W, Self::H, true);
+                const RIGHT_EDGE_MASK: <Self as Mode>::Bitset = constants::vertical_edge_mask::<{Self::N}, {Self::L}>(Self::W, Self::H, false);
+
+                const MOVE_TABLE: [[Option<u16>; 4]; Self::N] = constants::precompute_moves::<{Self::N}, {Self::L}>(Self::W, Self::H, Self::WRAP);
+            }
+
+            $(#[$attr])*
+            impl Mode for $name {
+                type Bitset = crate::bitboard::bitset::Bitset<{Self::N}, {Self::L}>;
+
+                fn with_dimensions(w: usize, h: usize, wrap: bool) -> Self {
+                    assert!(w == Self::W && h == Self::H && wrap == Self::WRAP, "board does not match mode {}", stringify!($name));
+                    $name{}
+                }
+
+                #[inline(always)] fn w(&self) -> usize { Self::W }
+                #[inline(always)] fn h(&self) -> usize { Self::H }
+                #[inline(always)] fn wrap(&self) -> bool { Self::WRAP }
+                #[inline(always)] fn n(&self) -> usize { Self::N }
+
+                #[inline(always)] fn full_board_mask(&self) -> Self::Bitset { Self::FULL_BOARD_MASK }
+                #[inline(always)] fn checker_board_mask(&self) -> Self::Bitset { Self::CHECKER_BOARD_MASK }
+                #[inline(always)] fn all_but_left_edge_mask(&self) -> Self::Bitset { Self::ALL_BUT_LEFT_EDGE_MASK }
+                #[inline(always)] fn all_but_right_edge_mask(&self) -> Self::Bitset { Self::ALL_BUT_RIGHT_EDGE_MASK }
+                #[inline(always)] fn top_edge_mask(&self) -> Self::Bitset { Self::TOP_EDGE_MASK }
+                #[inline(always)] fn bottom_edge_mask(&self) -> Self::Bitset { Self::BOTTOM_EDGE_MASK }
+                #[inline(always)] fn left_edge_mask(&self) -> Self::Bitset { Self::LEFT_EDGE_MASK }
+                #[inline(always)] fn right_edge_mask(&self) -> Self::Bitset { Self::RIGHT_EDGE_MASK }
+
+                #[inline(always)]
+                fn moves_from_position(&self, pos: u16) -> [Option<u16>; 4] {
+                    Self::MOVE_TABLE[pos as usize]
+                }
+            }
+        )*
+
+        /// Runs the visitor on the fastest mode that supports the gamestate.
+        /// Returns None if no mode can represent the gamestate's board or snake count.
+        pub fn dispatch<V: ModeVisitor>(state: wire_rep::GameState, visitor: V) -> Option<V::Output> {
+            let snake_count = state.board.snakes.len();
+            let dimensions = (state.board.width, state.board.height, is_wrapped(&state));
+            $(
+                $(#[$attr])*
+                if dimensions == ($name::W, $name::H, $name::WRAP) {
+                    $(
+                        if snake_count <= $s {
+                            return Some(visitor.visit::<$s, $name>(state))
+                        }
+                    )+
+                }
+            )*
+            if dimensions.0 * dimensions.1 <= DYNAMIC_MAX_CELLS {
+                $(
+                    if snake_count <= $fs {
+                        return Some(visitor.visit::<$fs, $fallback>(state))
+                    }
+                )+
+            }
+            None
+        }
+    };
+}
+
+modes! {
+    fallback Dynamic: snakes: [1, 2, 3, 4, 8, 16];
+
+    #[cfg(not(feature = "spl"))]
+    Standard: 11 x 11, wrap: false, snakes: [1, 2, 3, 4];
+    #[cfg(not(feature = "spl"))]
+    StandardWrapped: 11 x 11, wrap: true, snakes: [1, 2, 3, 4];
+
+    #[cfg(feature = "spl")]
+    Standard: 11 x 11, wrap: false, snakes: [1, 2, 3, 4, 5, 6, 7, 8];
+    #[cfg(feature = "spl")]
+    StandardWrapped: 11 x 11, wrap: true, snakes: [1, 2, 3, 4, 5, 6, 7, 8];
+    #[cfg(feature = "spl")]
+    Small: 7 x 7, wrap: false, snakes: [1, 2, 3, 4, 5, 6, 7, 8];
+    #[cfg(feature = "spl")]
+    SmallWrapped: 7 x 7, wrap: true, snakes: [1, 2, 3, 4, 5, 6, 7, 8];
+    #[cfg(feature = "spl")]
+    Large: 19 x 19, wrap: false, snakes: [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16];
+    #[cfg(feature = "spl")]
+    LargeWrapped: 19 x 19, wrap: true, snakes: [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16];
+    #[cfg(feature = "spl")]
+    ArcadeMaze: 19 x 21, wrap: true, snakes: [1, 2, 3, 4];
+    #[cfg(feature = "spl")]
+    Huge: 25 x 25, wrap: false, snakes: [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16];
+    #[cfg(feature = "spl")]
+    HugeWrapped: 25 x 25, wrap: true, snakes: [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16];
+}
+
+/// The largest number of cells a `Dynamic` board can hold.
+pub const DYNAMIC_MAX_CELLS: usize = 1024;
+const DYNAMIC_L: usize = DYNAMIC_MAX_CELLS / 64;
+type DynamicBitset = crate::bitboard::bitset::Bitset<DYNAMIC_MAX_CELLS, DYNAMIC_L>;
+
+/// Precomputed masks and moves of a `Dynamic` board.
+struct DynamicLayout {
+    w: usize,
+    h: usize,
+    wrap: bool,
+    full_board_mask: DynamicBitset,
+    checker_board_mask: DynamicBitset,
+    all_but_left_edge_mask: DynamicBitset,
+    all_but_right_edge_mask: DynamicBitset,
+    top_edge_mask: DynamicBitset,
+    bottom_edge_mask: DynamicBitset,
+    left_edge_mask: DynamicBitset,
+    right_edge_mask: DynamicBitset,
+    move_table: Box<[[Option<u16>; 4]]>,
 }
 
+/// A mode whose dimensions are chosen at runtime.
+/// Supports any board with up to `DYNAMIC_MAX_CELLS` cells, but is slower than the
+/// specialized modes, so it should only be used if none of them match.
 #[derive(Clone)]
-pub struct StandardWrapped{}
-impl Mode for StandardWrapped {
-    const W: usize = 11;
-    const H: usize = 11;
-    const WRAP: bool = true;
-
-    const N: usize = Self::W * Self::H;
-    const L: usize = (Self::W*Self::H+63)/64;
-    type Bitset = crate::bitboard::bitset::Bitset<{Self::N}, {Self::L}>;
-        
-    const FULL_BOARD_MASK: Self::Bitset = Self::Bitset::with_all_bits_set();
-    const CHECKER_BOARD_MASK: Self::Bitset = constants::checker_board_mask::<{Self::N}, {Self::L}>(Self::W, Self::H);
-    const ALL_BUT_LEFT_EDGE_MASK: Self::Bitset = constants::border_mask::<{Self::N}, {Self::L}>(Self::W, Self::H, true);
-    const ALL_BUT_RIGHT_EDGE_MASK: Self::Bitset = constants::border_mask::<{Self::N}, {Self::L}>(Self::W, Self::H, false);
-    const TOP_EDGE_MASK: Self::Bitset = constants::horizontal_edge_mask::<{Self::N}, {Self::L}>(Self::W, Self::H, true);
-    const BOTTOM_EDGE_MASK: Self::Bitset = constants::horizontal_edge_mask::<{Self::N}, {Self::L}>(Self::W, Self::H, false);
-    const LEFT_EDGE_MASK: Self::Bitset = constants::vertical_edge_mask::<{Self::N}, {Self::L}>(Self::W, Self::H, true);
-    const RIGHT_EDGE_MASK: Self::Bitset = constants::vertical_edge_mask::<{Self::N}, {Self::L}>(Self::W, Self::H, false);
-
-    type MoveTable = [[Option<u16>; 4]; Self::N];
-    const MOVE_TABLE: Self::MoveTable = constants::precompute_moves::<{Self::N}, {Self::L}>(Self::W, Self::H, Self::WRAP);
-
-    fn moves_from_position(pos: u16) -> [Option<u16>; 4] {
-        Self::MOVE_TABLE[pos as usize]
+pub struct Dynamic(Arc<DynamicLayout>);
+
+impl Mode for Dynamic {
+    type Bitset = DynamicBitset;
+
+    fn with_dimensions(w: usize, h: usize, wrap: bool) -> Self {
+        assert!(w > 0 && h > 0 && w * h <= DYNAMIC_MAX_CELLS, "board of size {}x{} is too large for mode Dynamic", w, h);
+        let move_table = constants::precompute_moves::<DYNAMIC_MAX_CELLS, DYNAMIC_L>(w, h, wrap);
+        Dynamic(Arc::new(DynamicLayout{
+            w,
+            h,
+            wrap,
+            full_board_mask: constants::full_board_mask::<DYNAMIC_MAX_CELLS, DYNAMIC_L>(w, h),
+            checker_board_mask: constants::checker_board_mask::<DYNAMIC_MAX_CELLS, DYNAMIC_L>(w, h),
+            all_but_left_edge_mask: constants::border_mask::<DYNAMIC_MAX_CELLS, DYNAMIC_L>(w, h, true),
+            all_but_right_edge_mask: constants::border_mask::<DYNAMIC_MAX_CELLS, DYNAMIC_L>(w, h, false),
+            top_edge_mask: constants::horizontal_edge_mask::<DYNAMIC_MAX_CELLS, DYNAMIC_L>(w, h, true),
+            bottom_edge_mask: constants::horizontal_edge_mask::<DYNAMIC_MAX_CELLS, DYNAMIC_L>(w, h, false),
+            left_edge_mask: constants::vertical_edge_mask::<DYNAMIC_MAX_CELLS, DYNAMIC_L>(w, h, true),
+            right_edge_mask: constants::vertical_edge_mask::<DYNAMIC_MAX_CELLS, DYNAMIC_L>(w, h, false),
+            move_table: move_table[..w*h].into(),
+        }))
+    }
+
+    #[inline(always)] fn w(&self) -> usize { self.0.w }
+    #[inline(always)] fn h(&self) -> usize { self.0.h }
+    #[inline(always)] fn wrap(&self) -> bool { self.0.wrap }
+
+    #[inline(always)] fn full_board_mask(&self) -> Self::Bitset { self.0.full_board_mask }
+    #[inline(always)] fn checker_board_mask(&self) -> Self::Bitset { self.0.checker_board_mask }
+    #[inline(always)] fn all_but_left_edge_mask(&self) -> Self::Bitset { self.0.all_but_left_edge_mask }
+    #[inline(always)] fn all_but_right_edge_mask(&self) -> Self::Bitset { self.0.all_but_right_edge_mask }
+    #[inline(always)] fn top_edge_mask(&self) -> Self::Bitset { self.0.top_edge_mask }
+    #[inline(always)] fn bottom_edge_mask(&self) -> Self::Bitset { self.0.bottom_edge_mask }
+    #[inline(always)] fn left_edge_mask(&self) -> Self::Bitset { self.0.left_edge_mask }
+    #[inline(always)] fn right_edge_mask(&self) -> Self::Bitset { self.0.right_edge_mask }
+
+    #[inline(always)]
+    fn moves_from_position(&self, pos: u16) -> [Option<u16>; 4] {
+        self.0.move_table[pos as usize]
     }
 }
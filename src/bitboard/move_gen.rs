@@ -7,12 +7,12 @@ pub fn allowed_moves<const S: usize, MODE: Mode>(board: &Bitboard<S, MODE>, snak
     let mut some_legal_move = Move::Up;
     let mut some_better_legal_move = None;
     let pos = board.snakes[snake_index].head;
-    let survives_hazard = board.snakes[snake_index].health > board.hazard_dmg;
+    let health = board.snakes[snake_index].health as i16;
 
-    for (mv_int, optional_dest) in MODE::moves_from_position(pos).iter().enumerate() {
+    for (mv_int, optional_dest) in board.mode.moves_from_position(pos).iter().enumerate() {
         if let Some(dest) = *optional_dest {
             some_legal_move = Move::from_int(mv_int as u8);
-            if survives_hazard || !board.hazard_mask.get(dest as usize) || board.food.get(dest as usize) {
+            if !board.hazard_mask.get(dest as usize) || health > board.hazard_damage(dest as usize) || board.food.get(dest as usize) {
                 some_better_legal_move = Some(some_legal_move);
                 if !board.bodies[0].get(dest as usize) {
                     moves.push(some_legal_move);
@@ -37,43 +37,43 @@ pub fn old_allowed_moves<const S: usize, MODE: Mode>(board: &Bitboard<S, MODE>,
     let mut moves = ArrayVec::<Move, 4>::new();
     let mut some_legal_move = Move::Left;
 
-    if MODE::WRAP {
-        let move_to = (pos as usize + MODE::W) % (MODE::W*MODE::H);
+    if board.mode.wrap() {
+        let move_to = (pos as usize + board.mode.w()) % (board.mode.w()*board.mode.h());
         if !board.bodies[0].get(move_to) {
             moves.push(Move::Up);
         }
-        let move_to = if MODE::W > pos as usize { MODE::W*(MODE::H-1) + pos as usize } else { pos as usize - MODE::W };
+        let move_to = if board.mode.w() > pos as usize { board.mode.w()*(board.mode.h()-1) + pos as usize } else { pos as usize - board.mode.w() };
         if !board.bodies[0].get(move_to) {
             moves.push(Move::Down);
         }
-        let move_to = if pos as usize % MODE::W == MODE::W-1 { pos as usize - (MODE::W-1) } else { pos as usize + 1};
+        let move_to = if pos as usize % board.mode.w() == board.mode.w()-1 { pos as usize - (board.mode.w()-1) } else { pos as usize + 1};
         if !board.bodies[0].get(move_to) {
             moves.push(Move::Right);
         }
-        let move_to = if pos as usize % MODE::W == 0 { pos as usize + (MODE::W-1) } else { pos as usize - 1 };
+        let move_to = if pos as usize % board.mode.w() == 0 { pos as usize + (board.mode.w()-1) } else { pos as usize - 1 };
         if !board.bodies[0].get(move_to) {
             moves.push(Move::Left);
         }
     } else {
-        if pos < (MODE::W * (MODE::H-1)) as u16 {
+        if pos < (board.mode.w() * (board.mode.h()-1)) as u16 {
             some_legal_move = Move::Up;
-            if !board.bodies[0].get(pos as usize + MODE::W) {
+            if !board.bodies[0].get(pos as usize + board.mode.w()) {
                 moves.push(Move::Up);
             }
         }
-        if pos >= MODE::W as u16 {
+        if pos >= board.mode.w() as u16 {
             some_legal_move = Move::Down;
-            if !board.bodies[0].get(pos as usize - MODE::W) {
+            if !board.bodies[0].get(pos as usize - board.mode.w()) {
                 moves.push(Move::Down);
             }
         }
-        if pos % (MODE::W as u16) < (MODE::W as u16 - 1) {
+        if pos % (board.mode.w() as u16) < (board.mode.w() as u16 - 1) {
             some_legal_move = Move::Right;
             if !board.bodies[0].get(pos as usize + 1) {
                 moves.push(Move::Right);
             }
         }
-        if pos % (MODE::W as u16) > 0 {
+        if pos % (board.mode.w() as u16) > 0 {
             some_legal_move = Move::Left;
             if !board.bodies[0].get(pos as usize - 1) {
                 moves.push(Move::Left);
@@ -92,12 +92,13 @@ pub fn ordered_allowed_moves<const S: usize, MODE: Mode>(
     history: &[[u64; 4]]
 ) -> ArrayVec<Move, 4> {
     let mut moves = allowed_moves(board, snake_index);
+    let blocked = board.bodies[0] | board.hazards_dealing(90);
     moves.sort_by_key(|mv| {
-        let dest = MODE::moves_from_position(board.snakes[snake_index].head)[mv.to_int() as usize].unwrap();
+        let dest = board.mode.moves_from_position(board.snakes[snake_index].head)[mv.to_int() as usize].unwrap();
         let mut options = 1;
         for i in 0..4 {
-            if let Some(pos) = MODE::moves_from_position(dest)[i] {
-                options += (!board.bodies[0].get(pos as usize) && (board.hazard_dmg < 90 || !board.hazard_mask.get(pos as usize))) as u64;
+            if let Some(pos) = board.mode.moves_from_position(dest)[i] {
+                options += !blocked.get(pos as usize) as u64;
             }
         }
         for snake in board.snakes {
@@ -110,6 +111,21 @@ pub fn ordered_allowed_moves<const S: usize, MODE: Mode>(
     moves
 }
 
+/// Reduces an ally's moves to the single move it is expected to play.
+/// Allies cooperate with us instead of playing against us, so there is no need to branch on
+/// their moves. They prefer the first move that stays clear of the cells our head can move to.
+fn cooperative_moves<const S: usize, MODE: Mode>(board: &Bitboard<S, MODE>, snake_index: usize, moves: ArrayVec<Move, 4>) -> ArrayVec<Move, 4> {
+    let head = board.snakes[snake_index].head;
+    let my_head = board.snakes[0].head;
+    let mv = moves.iter().copied().find(|mv| {
+        let dest = board.mode.moves_from_position(head)[mv.to_int() as usize].unwrap();
+        board.snakes[0].is_dead() || board.distance(my_head, dest) > 1
+    }).unwrap_or(moves[0]);
+    let mut moves = ArrayVec::new();
+    moves.push(mv);
+    moves
+}
+
 /// Generates up to 4 move combinations from a position, such that every move for every snake has
 /// been covered at least once.
 /// Can skip the first n snakes, their moves will always be Up in the result.
@@ -125,7 +141,7 @@ pub fn limited_move_combinations<const S: usize, MODE: Mode>(board: &Bitboard<S,
         let mut x = 0;
         let mut some_legal_move = Move::Up;
         for j in 0..4 {
-            if let Some(pos) = MODE::moves_from_position(board.snakes[i].head)[j] {
+            if let Some(pos) = board.mode.moves_from_position(board.snakes[i].head)[j] {
                 some_legal_move = Move::from_int(j as u8);
                 if !board.bodies[0].get(pos as usize) {
                     if moves.len() == x {
@@ -159,6 +175,9 @@ pub fn ordered_limited_move_combinations<const S: usize, MODE: Mode>(
         if snake.is_alive() {
             i += 1;
             let mut moves = ordered_allowed_moves(board, j+skip, history);
+            if board.is_ally(0, j+skip) {
+                moves = cooperative_moves(board, j+skip, moves);
+            }
             moves_per_snake.push(moves);
         } else {
             let mut none_move = ArrayVec::<_, 4>::new();
@@ -196,6 +215,9 @@ pub fn brs_move_combinations<const S: usize, MODE: Mode>(
         if snake.is_alive() {
             i += 1;
             let mut moves = ordered_allowed_moves(board, j+SKIP, history);
+            if board.is_ally(0, j+SKIP) {
+                moves = cooperative_moves(board, j+SKIP, moves);
+            }
             moves_per_snake.push(moves);
         } else {
             let mut none_move = ArrayVec::<_, 4>::new();
@@ -228,7 +250,9 @@ pub fn move_combinations<const S: usize, MODE: Mode>(board: &Bitboard<S, MODE>,
     // get moves for each enemy
     let mut moves_per_snake = ArrayVec::<ArrayVec<Move, 4>, S>::new();
     for (j, snake) in board.snakes[skip..].iter().enumerate() {
-        if snake.is_alive() {
+        if snake.is_alive() && board.is_ally(0, j+skip) {
+            moves_per_snake.push(cooperative_moves(board, j+skip, allowed_moves(board, j+skip)));
+        } else if snake.is_alive() {
             moves_per_snake.push(allowed_moves(board, j+skip));
         } else {
             let mut none_move = ArrayVec::<_, 4>::new();
@@ -281,6 +305,27 @@ pub fn moves_from_gamestate<const S: usize>(state: &wire_rep::GameState) -> [Mov
     moves
 }
 
+/// Picks a move straight from a gamestate that may not have passed validation.
+/// It prefers staying on the board and off of all snake bodies and falls back to Up.
+pub fn fallback_move(state: &wire_rep::GameState) -> Move {
+    let (w, h) = (state.board.width as i32, state.board.height as i32);
+    let wrapped = state.game.ruleset.name.is_wrapped();
+    let head = &state.you.head;
+    for (mv, (dx, dy)) in [(Move::Up, (0, 1)), (Move::Down, (0, -1)), (Move::Right, (1, 0)), (Move::Left, (-1, 0))] {
+        let mut dest = wire_rep::Coord{x: head.x + dx, y: head.y + dy};
+        if wrapped && w > 0 && h > 0 {
+            dest = wire_rep::Coord{x: dest.x.rem_euclid(w), y: dest.y.rem_euclid(h)};
+        }
+        if dest.x < 0 || dest.y < 0 || dest.x >= w || dest.y >= h {
+            continue
+        }
+        if !state.board.snakes.iter().any(|snake| snake.body.contains(&dest)) {
+            return mv
+        }
+    }
+    Move::Up
+}
+
 #[cfg(test)]
 mod tests {
     use crate::bitboard::mode::StandardWrapped;
@@ -294,6 +339,31 @@ mod tests {
         Bitboard::<4, StandardWrapped>::from_str(&val).unwrap()
     }
 
+    #[test]
+    fn test_fallback_move() {
+        let mut state = create_board().to_gamestate();
+        assert_eq!(fallback_move(&state), Move::Up);
+        state.board.snakes[1].body.push(wire_rep::Coord{x: 6, y: 3});
+        assert_eq!(fallback_move(&state), Move::Right);
+
+        // edges wrap only if the ruleset says so
+        state.you.head = wire_rep::Coord{x: 10, y: 10};
+        assert_eq!(fallback_move(&state), Move::Up);
+        state.game.ruleset.name = wire_rep::RulesetName::Standard;
+        assert_eq!(fallback_move(&state), Move::Down);
+
+        // requests that don't parse are read in part
+        let mut value = serde_json::to_value(&state).unwrap();
+        value["turn"] = "late".into();
+        value["you"]["health"] = serde_json::Value::Null;
+        assert!(serde_json::from_value::<wire_rep::GameState>(value.clone()).is_err());
+        assert_eq!(fallback_move(&wire_rep::GameState::from_partial_json(&value)), Move::Down);
+
+        // nothing sensible to do off the board
+        state.you.head = wire_rep::Coord{x: -5, y: 3};
+        assert_eq!(fallback_move(&state), Move::Up);
+    }
+
     #[bench]
     fn bench_enemy_move_generation(b: &mut Bencher) {
         let board = create_board();
@@ -308,7 +378,7 @@ mod tests {
         let mut rng = Pcg64Mcg::new(91825765198273048172569872943871926276_u128);
         b.iter(|| {
             let moves = random_move_combination(&board, &mut rng);
-            (board.apply_moves.clone())(&mut board, &moves);
+            board.play_moves(&moves);
         });
     }
 }
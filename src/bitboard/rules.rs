@@ -1,51 +1,120 @@
 use arrayvec::ArrayVec;
-use std::sync::Arc;
 use super::*;
 use rand::Rng;
-use rand_pcg::Pcg64Mcg;
-use crate::wire_rep;
-
-pub fn attach_rules<const S: usize, MODE: Mode>(
-    board: &mut Bitboard<S, MODE>,
-    api_state: &wire_rep::GameState
-) {
-    board.apply_moves = match api_state.game.ruleset["name"].as_str() {
-        Some("constrictor") | Some("wrapped-constrictor") => Arc::new(|board, moves| {
-            board.turn += 1;
+use crate::wire_rep::{self, RulesetName};
+
+/// The rules that advance a board by one turn.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Rules {
+    #[default]
+    Standard,
+    /// Tails never move, so snakes grow every turn. Wrapped constrictor plays by the same rules,
+    /// the wrapping is handled by the board's mode.
+    Constrictor,
+    /// Standard rules, plus a hazard spiral that grows around the center.
+    Spiral { center: u16 },
+    /// Standard rules, plus a safe zone that loses one of its edges every few turns.
+    Royale { shrink_every: u16 },
+    /// Standard rules, plus a sinkhole that grows in rings from the center every few turns.
+    Sinkholes { spawn_every: u16, max_rings: u16 },
+    /// Standard rules, plus a decaying trail of stacked hazards behind the tails.
+    SnailMode,
+    /// Standard rules, where hazards are healing pools that are used up by the snakes that heal
+    /// on them. Every `shrink_every` turns one more pool dries up, never if 0.
+    HealingPools { shrink_every: u16 },
+    /// Standard rules, where the snakes of a squad can share their fate, health and length,
+    /// as configured in the ruleset's squad settings.
+    /// If allowed, squad members can move through each other's bodies.
+    Squad,
+}
+
+impl Rules {
+    pub fn from_gamestate(state: &wire_rep::GameState) -> Self {
+        let shrink_every = state.game.ruleset.settings.royale.shrink_every_n_turns;
+        match state.game.ruleset.name {
+            RulesetName::Constrictor | RulesetName::WrappedConstrictor => Self::Constrictor,
+            RulesetName::Squad => Self::Squad,
+            _ => match state.game.map.as_str() {
+                "hz_spiral" if !state.board.hazards.is_empty() => Self::Spiral{
+                    center: (state.board.width*state.board.hazards[0].y as usize + state.board.hazards[0].x as usize) as u16,
+                },
+                "snail_mode" => Self::SnailMode,
+                "healing_pools" => Self::HealingPools{shrink_every},
+                "sinkholes" => Self::Sinkholes{
+                    spawn_every: if shrink_every > 0 { shrink_every } else { 10 },
+                    max_rings: match state.board.width {
+                        7 => 3,
+                        19 => 7,
+                        _ => 5,
+                    },
+                },
+                "royale" if shrink_every > 0 => Self::Royale{shrink_every},
+                // Rivers and bridges, columns and rings place all of their hazards when the game
+                // starts, so the hazards in the request never change. Scatter fill adds hazards on
+                // random cells, which we don't predict, just like unsampled food spawns.
+                _ => Self::Standard,
+            },
+        }
+    }
+}
+
+/// Advances the board by one turn, in which the snakes make the given moves.
+pub fn apply_moves<const S: usize, MODE: Mode>(board: &mut Bitboard<S, MODE>, moves: &[Move; S]) {
+    board.turn += 1;
+    match board.rules {
+        Rules::Standard => {
+            standard_turn::<S, MODE>(board, moves);
+        },
+        Rules::Constrictor => {
             move_heads::<S, MODE>(board, moves);
             let collided = perform_collisions::<S, MODE>(board);
             collided.into_iter().for_each(|snake_idx| board.kill_snake(snake_idx));
             finish_head_movement::<S, MODE>(board);
-        }),
-        _ => match api_state.game.map.as_str() {
-            "hz_spiral" if api_state.board.hazards.len() > 0 => {
-                let center = (api_state.board.width*api_state.board.hazards[0].y + api_state.board.hazards[0].x) as u16;
-                Arc::new(move |board, moves| {
-                    board.turn += 1;
-                    move_heads::<S, MODE>(board, moves);
-                    let starved = update_health::<S, MODE>(board);
-                    starved.into_iter().for_each(|snake_idx| board.kill_snake(snake_idx));
-                    move_tails::<S, MODE>(board);
-                    let collided = perform_collisions::<S, MODE>(board);
-                    collided.into_iter().for_each(|snake_idx| board.kill_snake(snake_idx));
-                    finish_head_movement::<S, MODE>(board);
-                    finish_tail_movement::<S, MODE>(board);
-                    inc_spiral_hazards::<S, MODE>(board, center);
-                })
-            },
-            _ => Arc::new(|board, moves| {
-                board.turn += 1;
-                move_heads::<S, MODE>(board, moves);
-                let starved = update_health::<S, MODE>(board);
-                starved.into_iter().for_each(|snake_idx| board.kill_snake(snake_idx));
-                move_tails::<S, MODE>(board);
-                let collided = perform_collisions::<S, MODE>(board);
-                collided.into_iter().for_each(|snake_idx| board.kill_snake(snake_idx));
-                finish_head_movement::<S, MODE>(board);
-                finish_tail_movement::<S, MODE>(board);
-            }),
         },
-    };
+        Rules::Spiral{center} => {
+            standard_turn::<S, MODE>(board, moves);
+            inc_spiral_hazards::<S, MODE>(board, center);
+        },
+        Rules::Royale{shrink_every} => {
+            standard_turn::<S, MODE>(board, moves);
+            shrink_royale_hazards::<S, MODE>(board, shrink_every);
+        },
+        Rules::Sinkholes{spawn_every, max_rings} => {
+            standard_turn::<S, MODE>(board, moves);
+            grow_sinkholes::<S, MODE>(board, spawn_every, max_rings);
+        },
+        Rules::SnailMode => {
+            let tails = board.snakes.map(|snake| snake.tail);
+            standard_turn::<S, MODE>(board, moves);
+            leave_snail_trails::<S, MODE>(board, &tails);
+        },
+        Rules::HealingPools{shrink_every} => {
+            standard_turn::<S, MODE>(board, moves);
+            use_healing_pools::<S, MODE>(board, shrink_every);
+        },
+        Rules::Squad => {
+            standard_turn::<S, MODE>(board, moves);
+            share_squad_attributes::<S, MODE>(board);
+        },
+    }
+    debug_check_zobrist_key::<S, MODE>(board);
+}
+
+fn standard_turn<const S: usize, MODE: Mode>(board: &mut Bitboard<S, MODE>, moves: &[Move; S]) {
+    move_heads::<S, MODE>(board, moves);
+    let starved = update_health::<S, MODE>(board);
+    starved.into_iter().for_each(|snake_idx| board.kill_snake(snake_idx));
+    move_tails::<S, MODE>(board);
+    let collided = perform_collisions::<S, MODE>(board);
+    collided.into_iter().for_each(|snake_idx| board.kill_snake(snake_idx));
+    finish_head_movement::<S, MODE>(board);
+    finish_tail_movement::<S, MODE>(board);
+}
+
+/// Checks that the incrementally updated zobrist key matches a recomputation, in debug builds only.
+#[inline(always)]
+fn debug_check_zobrist_key<const S: usize, MODE: Mode>(board: &Bitboard<S, MODE>) {
+    debug_assert_eq!(board.zobrist_key, board.compute_zobrist_key(), "zobrist key out of sync\n{:?}", board);
 }
 
 fn move_heads<const S: usize, MODE: Mode>(board: &mut Bitboard<S, MODE>, moves: &[Move; S]) {
@@ -57,36 +126,33 @@ fn move_heads<const S: usize, MODE: Mode>(board: &mut Bitboard<S, MODE>, moves:
         let mv_int = mv.to_int();
         // set direction of new body part
         let pos = board.snakes[i].head as usize;
-        board.bodies[1].set(pos, (mv_int&1) != 0);
-        board.bodies[2].set(pos, (mv_int>>1) != 0);
+        if let Some(crossing) = board.crossings.iter_mut().find(|c| c.pos as usize == pos && c.snake as usize == i) {
+            crossing.mv = Some(mv);
+        } else {
+            board.set_body(1, pos, (mv_int&1) != 0);
+            board.set_body(2, pos, (mv_int>>1) != 0);
+        }
         // set new head
-        board.snakes[i].head = if let Some(new_head) = MODE::moves_from_position(pos as u16)[mv_int as usize] {
-            new_head
+        if let Some(new_head) = board.mode.moves_from_position(pos as u16)[mv_int as usize] {
+            board.update_snake(i, |snake| snake.head = new_head);
         } else { // this snake has moved out of bounds
             board.kill_snake(i);
-            pos as u16
-        };
+        }
     }
 }
 
 fn move_tails<const S: usize, MODE: Mode>(board: &mut Bitboard<S, MODE>) {
     for i in 0..S {
-        let snake = &mut board.snakes[i];
+        let snake = board.snakes[i];
         if snake.is_dead() {
             continue
         }
         if snake.curled_bodyparts == 0 || snake.curled_bodyparts == 1 && snake.health == 100 {
-            let tail_move_int = board.bodies[1].get(snake.tail as usize) as u8 | (board.bodies[2].get(snake.tail as usize) as u8) << 1;
-            board.bodies[0].unset_bit(snake.tail as usize);
-            board.bodies[1].unset_bit(snake.tail as usize);
-            board.bodies[2].unset_bit(snake.tail as usize);
-            snake.tail = if MODE::WRAP {
-                snake.tail as i16 + Move::int_to_index_wrapping(tail_move_int, MODE::W, MODE::H, snake.tail)
-            } else {
-                snake.tail as i16 + Move::int_to_index(tail_move_int, MODE::W)
-            } as u16;
+            let new_tail = board.next_segment(i, snake.tail);
+            board.vacate(i, snake.tail);
+            board.update_snake(i, |snake| snake.tail = new_tail);
         } else {
-            snake.curled_bodyparts -= 1;
+            board.update_snake(i, |snake| snake.curled_bodyparts -= 1);
         }
     }
 }
@@ -95,32 +161,42 @@ fn update_health<const S: usize, MODE: Mode>(board: &mut Bitboard<S, MODE>) -> A
     let mut eaten = ArrayVec::<u16, S>::new();
     let mut starved = ArrayVec::<usize, S>::new();
     for i in 0..S {
-        let snake = &mut board.snakes[i];
-        if snake.is_dead() {
+        if board.snakes[i].is_dead() {
             continue
         }
-        // reduce health
-        let is_on_hazard = board.hazard_mask.get(snake.head as usize) as i8;
-        snake.health -= 1 + board.hazard_dmg * is_on_hazard;
+        let head = board.snakes[i].head;
+        let hazard_dmg = board.hazard_damage(head as usize);
+        let is_on_food = board.food.get(head as usize);
+        let is_starved = board.update_snake(i, |snake| {
+            // reduce health, stacked hazards can deal more damage than an i8 holds
+            // and healing hazards can't heal beyond full health
+            snake.health = (snake.health as i16 - 1 - hazard_dmg).clamp(0, 100) as i8;
 
-        // feed snake
-        if board.food.get(snake.head as usize) {
-            snake.health = 100;
-            snake.curled_bodyparts += 1;
-            snake.length += 1;
-            eaten.push(snake.head); // remember which food has been eaten
-        }
+            // feed snake
+            if is_on_food {
+                snake.health = 100;
+                snake.curled_bodyparts += 1;
+                snake.length += 1;
+            }
 
-        // starvation
-        if snake.is_dead() {
-            // mark snake for removal
-            snake.health = 1;
+            // starvation
+            if snake.is_dead() {
+                // mark snake for removal
+                snake.health = 1;
+                return true
+            }
+            false
+        });
+        if is_on_food {
+            eaten.push(head); // remember which food has been eaten
+        }
+        if is_starved {
             starved.push(i);
         }
     }
     // remove eaten food
     for food in eaten {
-        board.food.unset_bit(food as usize);
+        board.set_food(food as usize, false);
     }
     starved
 }
@@ -132,7 +208,7 @@ fn perform_collisions<const S: usize, MODE: Mode>(board: &mut Bitboard<S, MODE>)
             continue
         }
         // body collisions
-        if board.bodies[0].get(board.snakes[i].head as usize) {
+        if board.bodies[0].get(board.snakes[i].head as usize) && !cross_ally_body(board, i) {
             collided.push(i);
             continue
         }
@@ -154,6 +230,24 @@ fn perform_collisions<const S: usize, MODE: Mode>(board: &mut Bitboard<S, MODE>)
     collided
 }
 
+/// Lets a snake's head onto an ally's body, if the squad settings allow it.
+/// Returns false if any of the bodies on the cell is not an ally's.
+fn cross_ally_body<const S: usize, MODE: Mode>(board: &mut Bitboard<S, MODE>, snake_index: usize) -> bool {
+    if board.rules != Rules::Squad || !board.settings.squad.allow_body_collisions {
+        return false
+    }
+    let pos = board.snakes[snake_index].head;
+    let occupants = 1 + board.crossings.iter().filter(|c| c.pos == pos).count();
+    let allies = (0..S).filter(|&j| {
+        board.is_ally(snake_index, j) && board.snakes[j].is_alive() && board.is_on_body(j, pos)
+    }).count();
+    if allies < occupants {
+        return false  // our own body or an enemy's is in the way
+    }
+    board.crossings.push(Crossing{ pos, snake: snake_index as u8, mv: None });
+    true
+}
+
 fn finish_head_movement<const S: usize, MODE: Mode>(board: &mut Bitboard<S, MODE>) {
     for i in 0..S {
         if board.snakes[i].is_alive() {
@@ -163,31 +257,113 @@ fn finish_head_movement<const S: usize, MODE: Mode>(board: &mut Bitboard<S, MODE
             // set snake heads in bodies
             // we do this last, since it would break collision checks earlier, but we want this info
             // for move gen on the new board, since moving into the current space of a head is illegal
-            board.bodies[0].set_bit(board.snakes[i].head as usize);
+            board.set_body(0, board.snakes[i].head as usize, true);
         }
     }
 }
 
 fn finish_tail_movement<const S: usize, MODE: Mode>(board: &mut Bitboard<S, MODE>) {
     for i in 0..S {
-        if board.snakes[i].is_alive() && board.snakes[i].curled_bodyparts == 0 {
+        if board.snakes[i].is_alive() && board.snakes[i].curled_bodyparts == 0 && !board.is_shared(board.snakes[i].tail) {
             // unset tail bits for snakes that have no curled bodyparts 
             // we do this, since it is allowed to move there and we can effectively treat these
             // spaces as empty for the next move
             // we also do this last, since we need it earlier for collision checks of this turn
-            board.bodies[0].unset_bit(board.snakes[i].tail as usize);
+            // tails on shared cells stay, since the cell still holds an ally's body
+            board.set_body(0, board.snakes[i].tail as usize, false);
         }
     }
 }
 
-fn spawn_food<const S: usize, MODE: Mode>(board: &mut Bitboard<S, MODE>) {
-    let mut rng = Pcg64Mcg::new(91825765198273048172569872943871926276_u128);
-    if rng.gen_ratio(15, 100) {
-        let pos = rng.gen_range(0..(MODE::W*MODE::H));
-        if !board.bodies[0].get(pos) {
-            board.food.set_bit(pos);
+/// Makes the members of each squad share their elimination, their health and their length,
+/// by raising everyone to the squad's best value.
+fn share_squad_attributes<const S: usize, MODE: Mode>(board: &mut Bitboard<S, MODE>) {
+    let settings = board.settings.squad;
+    let squads = board.squads;
+    for squad in 0..S {
+        if squads[squad] as usize != squad {
+            continue
+        }
+        let members = || (0..S).filter(|i| squads[*i] as usize == squad);
+        let alive = members().filter(|i| board.snakes[*i].is_alive()).collect::<ArrayVec<usize, S>>();
+        if settings.shared_elimination && alive.len() < members().count() {
+            alive.into_iter().for_each(|i| board.kill_snake(i));
+            continue
+        }
+        let health = alive.iter().map(|i| board.snakes[*i].health).max().unwrap_or(0);
+        let length = alive.iter().map(|i| board.snakes[*i].length).max().unwrap_or(0);
+        for i in alive {
+            if settings.shared_health {
+                board.update_snake(i, |snake| snake.health = health);
+            }
+            let snake = board.snakes[i];
+            if settings.shared_length && snake.length < length {
+                // the missing length is stacked onto the tail
+                if snake.curled_bodyparts == 0 {
+                    board.set_body(0, snake.tail as usize, true);
+                }
+                board.update_snake(i, |snake| {
+                    snake.curled_bodyparts += length - snake.length;
+                    snake.length = length;
+                });
+            }
+        }
+    }
+}
+
+/// Returns the cells that food spawns on, if the map restricts it to a fixed set.
+fn food_spawn_points(gamemode: Gamemode) -> &'static [usize] {
+    match gamemode {
+        Gamemode::WrappedArcadeMaze => &[20, 36, 104, 137, 147, 212, 218, 224, 327, 332, 337],
+        _ => &[],
+    }
+}
+
+/// Spawns food the way the game does at the end of a turn.
+/// Food is topped up to the board's minimum food, otherwise one food spawns with the board's
+/// food spawn chance. It only spawns on free cells outside of hazards, or on the map's spawn points.
+pub fn spawn_food<const S: usize, MODE: Mode>(board: &mut Bitboard<S, MODE>, rng: &mut impl Rng) {
+    let food_count = board.food.count_ones() as usize;
+    let settings = board.settings;
+    let mut spawn_count = if food_count < settings.minimum_food as usize {
+        settings.minimum_food as usize - food_count
+    } else if settings.food_spawn_chance > 0 && rng.gen_ratio(settings.food_spawn_chance.min(100) as u32, 100) {
+        1
+    } else {
+        return
+    };
+
+    let spawn_points = food_spawn_points(board.gamemode);
+    let mut free = if spawn_points.is_empty() {
+        !board.hazard_mask & board.mode.full_board_mask()
+    } else {
+        let mut points = MODE::Bitset::new();
+        spawn_points.iter().for_each(|pos| points.set_bit(*pos));
+        points
+    };
+    free &= !board.bodies[0] & !board.food;
+    for snake in board.snakes {
+        if snake.is_alive() {
+            free.unset_bit(snake.tail as usize);
         }
     }
+
+    let mut free_count = free.count_ones() as usize;
+    while spawn_count > 0 && free_count > 0 {
+        let mut nth = rng.gen_range(0..free_count);
+        for pos in 0..board.mode.n() {
+            if free.get(pos) {
+                if nth == 0 {
+                    board.set_food(pos, true);
+                    free.unset_bit(pos);
+                    break
+                }
+                nth -= 1;
+            }
+        }
+        spawn_count -= 1;
+        free_count -= 1;
+    }
 }
 
 
@@ -201,9 +377,124 @@ fn inc_spiral_hazards<const S: usize, MODE: Mode>(board: &mut Bitboard<S, MODE>,
         return
     }
     let (x_shift, y_shift) = HAZARD_SPIRAL_SHIFTS[((board.turn/3)-1) as usize];
-    let x = center as i16 % MODE::W as i16 + x_shift as i16;
-    let y = center as i16 / MODE::W as i16 + y_shift as i16;
-    if x >= 0 && x < MODE::W as i16 && y >= 0 && y < MODE::H as i16 {
-        board.hazard_mask.set_bit((center as i16 + x_shift as i16 + y_shift as i16 * MODE::W as i16) as usize);
+    let x = center as i16 % board.mode.w() as i16 + x_shift as i16;
+    let y = center as i16 / board.mode.w() as i16 + y_shift as i16;
+    if x >= 0 && x < board.mode.w() as i16 && y >= 0 && y < board.mode.h() as i16 {
+        board.add_hazard((center as i16 + x_shift as i16 + y_shift as i16 * board.mode.w() as i16) as usize);
+    }
+}
+
+/// Shrinks the safe zone by one edge on every `shrink_every`th turn.
+/// The game picks the edge at random, we always assume the worst case, which is the edge that is
+/// closest to our head.
+fn shrink_royale_hazards<const S: usize, MODE: Mode>(board: &mut Bitboard<S, MODE>, shrink_every: u16) {
+    if board.turn < shrink_every || !board.turn.is_multiple_of(shrink_every) {
+        return
+    }
+    let w = board.mode.w();
+    let h = board.mode.h();
+    let (mut min_x, mut max_x, mut min_y, mut max_y) = (w, 0, h, 0);
+    for pos in 0..board.mode.n() {
+        if !board.hazard_mask.get(pos) {
+            min_x = min_x.min(pos % w);
+            max_x = max_x.max(pos % w);
+            min_y = min_y.min(pos / w);
+            max_y = max_y.max(pos / w);
+        }
+    }
+    if min_x > max_x {
+        return
+    }
+    let head_x = (board.snakes[0].head as usize % w).clamp(min_x, max_x);
+    let head_y = (board.snakes[0].head as usize / w).clamp(min_y, max_y);
+    // (distance to our head, whether the edge can shrink, cells of the edge)
+    let edges = [
+        (head_x - min_x, min_x < max_x, (min_x, min_x, min_y, max_y)),
+        (max_x - head_x, min_x < max_x, (max_x, max_x, min_y, max_y)),
+        (head_y - min_y, min_y < max_y, (min_x, max_x, min_y, min_y)),
+        (max_y - head_y, min_y < max_y, (min_x, max_x, max_y, max_y)),
+    ];
+    if let Some((_, _, (x0, x1, y0, y1))) = edges.into_iter().filter(|edge| edge.1).min_by_key(|edge| edge.0) {
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                board.set_hazard(0, w*y + x, true);
+            }
+        }
+    }
+}
+
+/// Grows the sinkhole in the center of the board by one ring every `spawn_every` turns.
+/// Every ring is a square without its corners that covers all the rings before it, so the
+/// hazards stack up towards the center.
+fn grow_sinkholes<const S: usize, MODE: Mode>(board: &mut Bitboard<S, MODE>, spawn_every: u16, max_rings: u16) {
+    // the map is updated with the turn number from before the move
+    let turn = board.turn as i32 - 1;
+    let spawn_every = spawn_every as i32;
+    let (w, h) = (board.mode.w() as i32, board.mode.h() as i32);
+    let (center_x, center_y) = (w / 2, h / 2);
+    if turn == 1 {
+        board.add_hazard((w*center_y + center_x) as usize);
+    }
+    if turn < 1 || turn > spawn_every * max_rings as i32 || (turn - 1) % spawn_every != 0 {
+        return
+    }
+    let ring = (turn - 1) / spawn_every;
+    if ring == 0 {
+        return
+    }
+    for y in (center_y - ring).max(0)..=(center_y + ring).min(h - 1) {
+        for x in (center_x - ring).max(0)..=(center_x + ring).min(w - 1) {
+            if (x - center_x).abs() == ring && (y - center_y).abs() == ring {
+                continue
+            }
+            board.add_hazard((w*y + x) as usize);
+        }
+    }
+}
+
+/// The height of the hazard stack that a tail leaves behind in snail mode.
+pub const SNAIL_TRAIL_LENGTH: usize = 7;
+
+/// Lets every hazard stack decay by one and leaves fresh trails where tails moved away.
+fn leave_snail_trails<const S: usize, MODE: Mode>(board: &mut Bitboard<S, MODE>, old_tails: &[u16; S]) {
+    for pos in 0..board.mode.n() {
+        if board.hazard_mask.get(pos) {
+            let stack = board.hazard_stack(pos) as usize;
+            board.set_hazard(stack - 1, pos, false);
+        }
+    }
+    for (i, &tail) in old_tails.iter().enumerate() {
+        if board.snakes[i].is_dead() || board.snakes[i].tail == tail {
+            continue
+        }
+        if board.snakes.iter().any(|snake| snake.is_alive() && snake.head == tail) {
+            continue
+        }
+        for _ in 0..SNAIL_TRAIL_LENGTH {
+            board.add_hazard(tail as usize);
+        }
+    }
+}
+
+/// Removes the healing pools that snakes healed on this turn, and one more pool on every
+/// `shrink_every`th turn. The game picks that pool at random, we always assume the worst case,
+/// which is the pool that is closest to our head.
+fn use_healing_pools<const S: usize, MODE: Mode>(board: &mut Bitboard<S, MODE>, shrink_every: u16) {
+    for i in 0..S {
+        let head = board.snakes[i].head as usize;
+        if board.snakes[i].is_alive() && board.hazard_mask.get(head) {
+            board.clear_hazards(head);
+        }
+    }
+    // the map is updated with the turn number from before the move
+    let turn = board.turn - 1;
+    if shrink_every == 0 || turn == 0 || !turn.is_multiple_of(shrink_every) {
+        return
+    }
+    let closest = (0..board.mode.n())
+        .filter(|pos| board.hazard_mask.get(*pos))
+        .min_by_key(|pos| board.distance(board.snakes[0].head, *pos as u16));
+    if let Some(pos) = closest {
+        board.clear_hazards(pos);
     }
 }
@@ -0,0 +1,69 @@
+//! Zobrist keys for bitboards.
+//! Instead of tables of random numbers, the key of each feature is derived by mixing its
+//! description into a random looking number. This works for any board size and snake count.
+
+use super::{Gamemode, Move, Snake};
+
+const BODY: u64 = 0;
+const FOOD: u64 = 3;
+const HAZARD: u64 = 4;
+const SNAKE: u64 = 5;
+const MOVE: u64 = 6;
+const GAME: u64 = 7;
+
+/// The splitmix64 finalizer.
+#[inline(always)]
+const fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e3779b97f4a7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+#[inline(always)]
+const fn feature(kind: u64, value: u64) -> u64 {
+    mix(kind << 61 | value)
+}
+
+/// The key of a set bit in one of the body layers.
+#[inline(always)]
+pub const fn body(layer: usize, pos: usize) -> u64 {
+    feature(BODY + layer as u64, pos as u64)
+}
+
+#[inline(always)]
+pub const fn food(pos: usize) -> u64 {
+    feature(FOOD, pos as u64)
+}
+
+/// The key of a set bit in one of the hazard stack levels.
+#[inline(always)]
+pub const fn hazard(level: usize, pos: usize) -> u64 {
+    feature(HAZARD, (level as u64) << 32 | pos as u64)
+}
+
+/// The key of a snake at an index, dead snakes don't contribute to the key.
+#[inline(always)]
+pub fn snake(index: usize, snake: &Snake) -> u64 {
+    if snake.is_dead() {
+        return 0
+    }
+    let packed = (snake.head as u64)
+        | (snake.tail as u64) << 16
+        | (snake.length as u64) << 32
+        | (snake.health as u8 as u64) << 40
+        | (snake.curled_bodyparts as u64) << 48
+        | (index as u64) << 56;
+    mix(packed ^ mix(SNAKE))
+}
+
+/// The key of a move that is about to be made, used to tell min nodes apart from max nodes.
+#[inline(always)]
+pub const fn mv(mv: Move) -> u64 {
+    feature(MOVE, mv.to_int() as u64)
+}
+
+/// The key of the game's parameters, which don't change during a game.
+pub fn game(gamemode: Gamemode, hazard_dmg: i8) -> u64 {
+    feature(GAME, (gamemode as u64) << 8 | hazard_dmg as u8 as u64)
+}
@@ -0,0 +1,372 @@
+use serde::{Deserialize, Serialize, Serializer};
+use std::env;
+use std::fs;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use crate::bitboard::Gamemode;
+use crate::engine::EngineId;
+
+/// The configuration file is read from this environment variable's path, without it only defaults are used.
+pub const CONFIG_FILE_VAR: &str = "CONFIG_FILE";
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Everything that can be tuned without recompiling.
+/// Every section and field is optional in the file and falls back to the defaults.
+#[derive(Clone, Deserialize, Serialize, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub telemetry: TelemetryConfig,
+    pub timing: TimingConfig,
+    pub search: SearchConfig,
+    pub engines: EngineConfig,
+    pub appearance: Appearance,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub port: u16,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig{ port: 8080 }
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelemetryConfig {
+    /// Traces are exported to honeycomb if a key is set, otherwise they are logged to stdout.
+    #[serde(serialize_with = "redact")]
+    pub honeycomb_key: Option<String>,
+    pub honeycomb_endpoint: String,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        TelemetryConfig{
+            honeycomb_key: None,
+            honeycomb_endpoint: "https://api.honeycomb.io".to_string(),
+        }
+    }
+}
+
+/// All margins are in milliseconds before the game's timeout, minus the network latency.
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimingConfig {
+    /// When the search stops.
+    pub search_margin_ms: u32,
+    /// When the watchdog answers, no matter what the search is doing.
+    pub watchdog_margin_ms: u32,
+    /// The network latency that is assumed until the engine reported one for the game.
+    pub default_latency_ms: u32,
+}
+
+impl Default for TimingConfig {
+    fn default() -> Self {
+        TimingConfig{
+            search_margin_ms: 30,
+            watchdog_margin_ms: 15,
+            default_latency_ms: 60,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SearchConfig {
+    /// Whether searches use a transposition table, pondering only works with one.
+    pub tt: bool,
+    /// The size of the transposition table in megabytes.
+    pub tt_size_mb: usize,
+    /// The number of threads used by the parallel search, including the main thread, 0 uses every core.
+    /// Without the transposition table, searches always run on a single thread.
+    pub threads: usize,
+    /// Searches to exactly this depth instead of until the deadline, if above 0.
+    pub fixed_depth: u8,
+    /// Searches for exactly this long instead of until the deadline, if above 0.
+    pub fixed_time_ms: u64,
+    /// Whether searches sample food spawns, instead of assuming that no food ever spawns.
+    pub sample_food_spawns: bool,
+    /// Pondering is only done while at most this many games are running, 0 disables it.
+    pub ponder_max_games: usize,
+    /// Whether MCTS picks the move when minimax sees a shallow loss in games with more than two snakes.
+    pub mcts_fallback: bool,
+    /// The exploration constant of UCB1 in MCTS.
+    pub ucb_c: f64,
+    pub quiescence: QuiescenceConfig,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        SearchConfig{
+            tt: true,
+            tt_size_mb: 256,
+            threads: 1,
+            fixed_depth: 0,
+            fixed_time_ms: 0,
+            sample_food_spawns: false,
+            ponder_max_games: 0,
+            mcts_fallback: true,
+            ucb_c: 1.5,
+            quiescence: QuiescenceConfig::default(),
+        }
+    }
+}
+
+/// The maximum depth of the quiescence search per kind of gamemode, 1 turns it off.
+/// Gamemodes that aren't listed don't use quiescence search.
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuiescenceConfig {
+    /// Standard, squad and wrapped islands and bridges games, which quiesce until no enemy head is close.
+    pub standard: u8,
+    pub snail_mode: u8,
+    pub arcade_maze: u8,
+    /// Games on hazard maps, which quiesce until no snake is about to eat.
+    pub hazards: u8,
+}
+
+impl Default for QuiescenceConfig {
+    fn default() -> Self {
+        QuiescenceConfig{
+            standard: 5,
+            snail_mode: 5,
+            arcade_maze: 20,
+            hazards: 3,
+        }
+    }
+}
+
+/// Chooses the engine that answers /move requests.
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct EngineConfig {
+    pub default: EngineId,
+    /// The first rule that matches a game overrides the default engine.
+    pub rules: Vec<EngineRule>,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        EngineConfig{
+            default: EngineId::Minimax,
+            rules: vec![],
+        }
+    }
+}
+
+/// Matches games by gamemode and number of snakes, conditions that are left out match every game.
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct EngineRule {
+    pub engine: EngineId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gamemode: Option<Gamemode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_snakes: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_snakes: Option<usize>,
+}
+
+impl EngineRule {
+    fn matches(&self, gamemode: Gamemode, snakes: usize) -> bool {
+        self.gamemode.is_none_or(|x| x == gamemode)
+            && self.min_snakes.is_none_or(|x| snakes >= x)
+            && self.max_snakes.is_none_or(|x| snakes <= x)
+    }
+}
+
+impl EngineConfig {
+    /// Returns the engine for a game of the gamemode with this many snakes alive.
+    pub fn select(&self, gamemode: Gamemode, snakes: usize) -> EngineId {
+        self.rules.iter()
+            .find(|rule| rule.matches(gamemode, snakes))
+            .map_or(self.default, |rule| rule.engine)
+    }
+}
+
+/// How the snake presents itself on the index route.
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Appearance {
+    pub author: String,
+    pub color: String,
+    pub head: String,
+    pub tail: String,
+}
+
+impl Default for Appearance {
+    fn default() -> Self {
+        Appearance{
+            author: "JonathanArns".to_string(),
+            color: "#900050".to_string(),
+            head: "cosmic-horror-special".to_string(),
+            tail: "cosmic-horror".to_string(),
+        }
+    }
+}
+
+fn redact<S: Serializer>(secret: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    secret.as_ref().map(|_| "<redacted>").serialize(serializer)
+}
+
+impl Config {
+    /// Parses a configuration file, missing values take their defaults.
+    pub fn from_toml(src: &str) -> Result<Self, Vec<String>> {
+        toml::from_str(src).map_err(|err| vec![err.to_string()])
+    }
+
+    /// Overrides values with the environment variables that configured them before there was a configuration file.
+    pub fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<(), Vec<String>> {
+        let mut errors = vec![];
+        let mut set = |name: &str, apply: &mut dyn FnMut(&str) -> bool| {
+            if let Some(value) = var(name) && !apply(&value) {
+                errors.push(format!("invalid value {:?} for {}", value, name));
+            }
+        };
+        set("PORT", &mut |x| parse_into(x, &mut self.server.port));
+        set("HONEYCOMB_KEY", &mut |x| { self.telemetry.honeycomb_key = Some(x.to_string()); true });
+        set("SEARCH_MARGIN_MS", &mut |x| parse_into(x, &mut self.timing.search_margin_ms));
+        set("WATCHDOG_MARGIN_MS", &mut |x| parse_into(x, &mut self.timing.watchdog_margin_ms));
+        set("DEFAULT_LATENCY_MS", &mut |x| parse_into(x, &mut self.timing.default_latency_ms));
+        set("TT_SIZE_MB", &mut |x| parse_into(x, &mut self.search.tt_size_mb));
+        set("SEARCH_THREADS", &mut |x| parse_into(x, &mut self.search.threads));
+        set("FIXED_DEPTH", &mut |x| parse_into(x, &mut self.search.fixed_depth));
+        set("FIXED_TIME", &mut |x| parse_into(x, &mut self.search.fixed_time_ms));
+        set("SAMPLE_FOOD_SPAWNS", &mut |x| parse_into(x, &mut self.search.sample_food_spawns));
+        set("PONDER_MAX_GAMES", &mut |x| parse_into(x, &mut self.search.ponder_max_games));
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Checks the values that can't be ruled out by their types alone.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = vec![];
+        if self.server.port == 0 {
+            errors.push("server.port must not be 0".to_string());
+        }
+        if self.telemetry.honeycomb_key.as_ref().is_some_and(|key| key.is_empty()) {
+            errors.push("telemetry.honeycomb_key must not be empty".to_string());
+        }
+        if self.timing.watchdog_margin_ms >= self.timing.search_margin_ms {
+            errors.push(format!(
+                "timing.watchdog_margin_ms ({}) must be below timing.search_margin_ms ({}), or the watchdog cuts every search short",
+                self.timing.watchdog_margin_ms, self.timing.search_margin_ms,
+            ));
+        }
+        if self.search.tt_size_mb == 0 {
+            errors.push("search.tt_size_mb must not be 0".to_string());
+        }
+        for (i, rule) in self.engines.rules.iter().enumerate() {
+            if rule.min_snakes.unwrap_or(0) > rule.max_snakes.unwrap_or(usize::MAX) {
+                errors.push(format!("engines.rules[{}] can never match, min_snakes is above max_snakes", i));
+            }
+        }
+        let quiescence = self.search.quiescence;
+        if [quiescence.standard, quiescence.snail_mode, quiescence.arcade_maze, quiescence.hazards].contains(&0) {
+            errors.push("search.quiescence depths must be at least 1".to_string());
+        }
+        if !(self.search.ucb_c.is_finite() && self.search.ucb_c > 0.0) {
+            errors.push(format!("search.ucb_c must be positive, got {}", self.search.ucb_c));
+        }
+        let color = &self.appearance.color;
+        if color.len() != 7 || !color.starts_with('#') || !color[1..].chars().all(|c| c.is_ascii_hexdigit()) {
+            errors.push(format!("appearance.color must look like #rrggbb, got {:?}", color));
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Loads the configuration file from CONFIG_FILE, applies environment overrides and validates the result.
+    pub fn load() -> Result<Self, Vec<String>> {
+        let mut config = match env::var(CONFIG_FILE_VAR) {
+            Ok(path) => {
+                let src = fs::read_to_string(&path).map_err(|err| vec![format!("failed to read {}: {}", path, err)])?;
+                Self::from_toml(&src)?
+            },
+            Err(_) => Self::default(),
+        };
+        config.apply_env(|name| env::var(name).ok())?;
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+fn parse_into<T: FromStr>(src: &str, field: &mut T) -> bool {
+    src.parse().map(|x| *field = x).is_ok()
+}
+
+/// Loads the configuration, this should be done at startup so that mistakes surface immediately.
+pub fn init() -> Result<&'static Config, Vec<String>> {
+    let config = Config::load()?;
+    Ok(CONFIG.get_or_init(|| config))
+}
+
+/// Returns the configuration, loading it on first use if init wasn't called.
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(|| Config::load().unwrap_or_else(|errors| panic!("invalid configuration: {}", errors.join(", "))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_example_config() {
+        let config = Config::from_toml(include_str!("../config.example.toml")).unwrap();
+        assert_eq!(config, Config::default());
+        assert_eq!(Config::from_toml("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn test_config_errors() {
+        let config = Config::from_toml(r#"
+            [telemetry]
+            honeycomb_key = "secret"
+            [[engines.rules]]
+            engine = "mcts"
+            min_snakes = 5
+            [[engines.rules]]
+            engine = "mcts"
+            gamemode = "squad"
+            max_snakes = 2
+        "#).unwrap();
+        assert_eq!(config.engines.select(Gamemode::Standard, 2), EngineId::Minimax);
+        assert_eq!(config.engines.select(Gamemode::StandardWithHazard, 16), EngineId::Mcts);
+        assert_eq!(config.engines.select(Gamemode::Squad, 2), EngineId::Mcts);
+        assert_eq!(config.engines.select(Gamemode::Squad, 4), EngineId::Minimax);
+        assert!(!serde_json::to_string(&config).unwrap().contains("secret"));
+
+        // typos and wrong types are errors instead of silent defaults
+        for src in ["[server]\nprot = 8000", "[search]\nthreads = -1", "[engines]\ndefault = \"alphazero\"", "[[engines.rules]]\nengine = \"mcts\"\ngamemode = \"chess\""] {
+            assert!(Config::from_toml(src).is_err(), "{}", src);
+        }
+
+        let mut config = Config::default();
+        let errors = config.apply_env(|name| match name {
+            "PORT" => Some("8000".to_string()),
+            "FIXED_DEPTH" => Some("deep".to_string()),
+            _ => None,
+        }).unwrap_err();
+        assert_eq!(config.server.port, 8000);
+        assert_eq!(errors, vec![r#"invalid value "deep" for FIXED_DEPTH"#]);
+
+        config.timing.watchdog_margin_ms = 50;
+        config.appearance.color = "purple".to_string();
+        config.engines.rules.push(EngineRule{ engine: EngineId::Mcts, gamemode: None, min_snakes: Some(3), max_snakes: Some(2) });
+        config.search.quiescence.hazards = 0;
+        assert_eq!(config.validate().unwrap_err().len(), 4);
+    }
+}
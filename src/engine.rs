@@ -0,0 +1,112 @@
+use serde::{Deserialize, Serialize};
+use std::time;
+
+use crate::bitboard::{Bitboard, Mode, Move};
+use crate::minimax::{self, Score};
+use crate::uct;
+
+/// A search algorithm that picks our move.
+pub trait Engine {
+    fn search<const S: usize, MODE: Mode>(&self, board: &Bitboard<S, MODE>, deadline: time::SystemTime) -> SearchResult<S>;
+
+    /// Keeps working on the position that we expect next after playing mv, until the deadline.
+    /// Engines that can't make use of the time between requests don't need to do anything.
+    fn ponder<const S: usize, MODE: Mode>(&self, _board: &Bitboard<S, MODE>, _mv: Move, _deadline: time::SystemTime) {}
+}
+
+/// What an engine found out about a position.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchResult<const S: usize> {
+    pub best_move: Move,
+    pub evaluation: Evaluation,
+    /// The depth that was searched to, 0 for engines that don't search to a fixed depth.
+    pub depth: u8,
+    /// The deepest ply that was reached, including search extensions and quiescence search.
+    pub seldepth: u8,
+    pub nodes: u64,
+    pub nodes_per_second: u64,
+    /// The share of transposition table lookups that found an entry.
+    pub tt_hit_rate: f64,
+    /// The line of play that the engine expects, starting with our best move.
+    /// Joint moves have our move at index 0, like everywhere else.
+    pub pv: Vec<[Move; S]>,
+    /// The evaluation of each of our moves at the root.
+    pub root_moves: Vec<(Move, Evaluation)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Evaluation {
+    /// A minimax score from our point of view.
+    Score(Score),
+    /// A minimax score that the true score is at least as good as.
+    LowerBound(Score),
+    /// A minimax score that the true score is at most as good as.
+    UpperBound(Score),
+    /// The share of simulated games that we won.
+    Winrate(f64),
+}
+
+/// Computes nodes per second without dividing by zero for very short searches.
+pub(crate) fn nodes_per_second(nodes: u64, elapsed: time::Duration) -> u64 {
+    (nodes as u128 * time::Duration::from_secs(1).as_nanos() / elapsed.as_nanos().max(1)) as u64
+}
+
+/// The registry of engines, which the configuration refers to by name.
+/// New engines are added as a variant here and in the match arms below.
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum EngineId {
+    Minimax,
+    Mcts,
+}
+
+impl EngineId {
+    pub fn search<const S: usize, MODE: Mode>(self, board: &Bitboard<S, MODE>, deadline: time::SystemTime) -> SearchResult<S> {
+        match self {
+            Self::Minimax => minimax::Minimax.search(board, deadline),
+            Self::Mcts => uct::Mcts.search(board, deadline),
+        }
+    }
+
+    pub fn ponder<const S: usize, MODE: Mode>(self, board: &Bitboard<S, MODE>, mv: Move, deadline: time::SystemTime) {
+        match self {
+            Self::Minimax => minimax::Minimax.ponder(board, mv, deadline),
+            Self::Mcts => uct::Mcts.ponder(board, mv, deadline),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitboard::move_gen::allowed_moves;
+    use crate::bitboard::tests::create_duel_board;
+
+
+    #[test]
+    fn test_search_result() {
+        let board = create_duel_board();
+        let mut allowed: Vec<u8> = allowed_moves(&board, 0).iter().map(|mv| mv.to_int()).collect();
+        allowed.sort();
+        for engine in [EngineId::Minimax, EngineId::Mcts] {
+            let result = engine.search(&board, time::SystemTime::now() + time::Duration::from_millis(200));
+            assert!(result.nodes > 0, "{:?}", engine);
+            assert!(result.seldepth > 0, "{:?}", engine);
+            assert!((0.0..=1.0).contains(&result.tt_hit_rate), "{:?}", engine);
+
+            // every root move is scored, and the best move is one of them
+            let mut root_moves: Vec<u8> = result.root_moves.iter().map(|(mv, _)| mv.to_int()).collect();
+            root_moves.sort();
+            assert_eq!(root_moves, allowed, "{:?}", engine);
+
+            // the pv starts with the best move and can be played out
+            assert_eq!(result.pv.first().map(|mvs| mvs[0]), Some(result.best_move), "{:?}", engine);
+            let mut b = board.clone();
+            for mvs in &result.pv {
+                assert!(!b.is_terminal(), "{:?}", engine);
+                assert!(b.is_legal_move(b.snakes[0].head, mvs[0]) && b.is_legal_enemy_moves(*mvs), "{:?}", engine);
+                b.play_moves(mvs);
+            }
+        }
+    }
+}
@@ -1,3 +1,7 @@
+#![cfg_attr(test, feature(test))]
+
+#[cfg(test)]
+extern crate test;
 #[macro_use]
 extern crate lazy_static;
 
@@ -6,10 +10,14 @@ pub mod wire_rep;
 pub mod api;
 pub mod minimax;
 pub mod uct;
+pub mod session;
+pub mod config;
+pub mod engine;
 
 pub fn init() {
-    #[cfg(feature = "tt")]
-    minimax::init()
+    if config::get().search.tt {
+        minimax::init()
+    }
 }
 
 #[cfg(feature = "training")]
@@ -1,4 +1,8 @@
 use crate::bitboard::{mode::Mode, *};
+use arrayvec::ArrayVec;
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 
 pub fn solver<const S: usize, MODE: Mode>(
     board: &Bitboard<S, MODE>,
@@ -34,17 +38,17 @@ fn _solver<const S: usize, MODE: Mode>(
     // make fill one bigger
     let my_area = my_area.clone();
     let enemy_area = enemy_area.clone();
-    let mut em_fill = my_area | (MODE::ALL_BUT_LEFT_EDGE_MASK & my_area)<<1 | (MODE::ALL_BUT_RIGHT_EDGE_MASK & my_area)>>1 | my_area<<MODE::W | my_area>>MODE::W;
-    let mut ee_fill = enemy_area | (MODE::ALL_BUT_LEFT_EDGE_MASK & enemy_area)<<1 | (MODE::ALL_BUT_RIGHT_EDGE_MASK & enemy_area)>>1 | enemy_area<<MODE::W | enemy_area>>MODE::W;
-    if MODE::WRAP {
-        em_fill |= (MODE::LEFT_EDGE_MASK & my_area) >> (MODE::W-1)
-            | (MODE::RIGHT_EDGE_MASK & my_area) << (MODE::W-1)
-            | (MODE::BOTTOM_EDGE_MASK & my_area) << ((MODE::H-1)*MODE::W)
-            | (MODE::TOP_EDGE_MASK & my_area) >> ((MODE::H-1)*MODE::W);
-        ee_fill |= (MODE::LEFT_EDGE_MASK & enemy_area) >> (MODE::W-1)
-            | (MODE::RIGHT_EDGE_MASK & enemy_area) << (MODE::W-1)
-            | (MODE::BOTTOM_EDGE_MASK & enemy_area) << ((MODE::H-1)*MODE::W)
-            | (MODE::TOP_EDGE_MASK & enemy_area) >> ((MODE::H-1)*MODE::W);
+    let mut em_fill = my_area | (board.mode.all_but_left_edge_mask() & my_area)<<1 | (board.mode.all_but_right_edge_mask() & my_area)>>1 | my_area<<board.mode.w() | my_area>>board.mode.w();
+    let mut ee_fill = enemy_area | (board.mode.all_but_left_edge_mask() & enemy_area)<<1 | (board.mode.all_but_right_edge_mask() & enemy_area)>>1 | enemy_area<<board.mode.w() | enemy_area>>board.mode.w();
+    if board.mode.wrap() {
+        em_fill |= (board.mode.left_edge_mask() & my_area) >> (board.mode.w()-1)
+            | (board.mode.right_edge_mask() & my_area) << (board.mode.w()-1)
+            | (board.mode.bottom_edge_mask() & my_area) << ((board.mode.h()-1)*board.mode.w())
+            | (board.mode.top_edge_mask() & my_area) >> ((board.mode.h()-1)*board.mode.w());
+        ee_fill |= (board.mode.left_edge_mask() & enemy_area) >> (board.mode.w()-1)
+            | (board.mode.right_edge_mask() & enemy_area) << (board.mode.w()-1)
+            | (board.mode.bottom_edge_mask() & enemy_area) << ((board.mode.h()-1)*board.mode.w())
+            | (board.mode.top_edge_mask() & enemy_area) >> ((board.mode.h()-1)*board.mode.w());
     }
     
     // determine, when a tail comes by to chase
@@ -126,3 +130,218 @@ fn _solver<const S: usize, MODE: Mode>(
         None
     }
 }
+
+/// The most nodes that a longest path search may visit for one snake, before giving up.
+const SPACE_FILL_BUDGET: usize = 2000;
+
+/// The number of longest path results that each thread remembers.
+const PATH_CACHE_SIZE: usize = 1 << 12;
+
+/// The hash of a path's start and area, and the path's length.
+type PathCacheEntry = (u64, Option<i16>);
+
+thread_local! {
+    /// Longest paths by the hash of their start and area, including the searches that ran out of budget.
+    /// Once the snakes are separated, a snake's area stays the same while the others move,
+    /// so the siblings of a leaf in the search tree mostly ask for the same paths.
+    static PATH_CACHE: RefCell<Vec<Option<PathCacheEntry>>> = RefCell::new(vec![None; PATH_CACHE_SIZE]);
+}
+
+/// Solves constrictor positions, in which no cell can be reached by more than one snake.
+/// Bodies never shrink in constrictor, so each snake survives exactly as many turns as the
+/// longest path through its own area is long. Returns None if the snakes aren't separated yet,
+/// or if a longest path is too expensive to find.
+pub fn constrictor_solver<const S: usize, MODE: Mode>(board: &Bitboard<S, MODE>) -> Option<i16> {
+    let walkable = !board.bodies[0] & board.mode.full_board_mask();
+    let mut reached = MODE::Bitset::new();
+    let mut my_turns = 0;
+    let mut enemy_turns = None;
+    for (i, snake) in board.snakes.iter().enumerate() {
+        if snake.is_dead() {
+            continue
+        }
+        let area = reachable_area(board, snake.head, walkable);
+        if (area & reached).any() {
+            return None
+        }
+        reached |= area;
+        let turns = cached_longest_path(board, snake.head, area)?;
+        if i == 0 {
+            my_turns = turns;
+        } else {
+            enemy_turns = enemy_turns.max(Some(turns));
+        }
+    }
+    let enemy_turns = enemy_turns?;
+    if my_turns > enemy_turns {
+        Some(i16::MAX - board.turn as i16 - enemy_turns)
+    } else if my_turns < enemy_turns {
+        Some(i16::MIN + board.turn as i16 + my_turns)
+    } else {
+        Some(0)
+    }
+}
+
+/// Returns the free cells that can be reached from pos.
+fn reachable_area<const S: usize, MODE: Mode>(board: &Bitboard<S, MODE>, pos: u16, walkable: MODE::Bitset) -> MODE::Bitset {
+    let mut area = MODE::Bitset::new();
+    let mut stack = vec![pos];
+    while let Some(pos) = stack.pop() {
+        for next in board.mode.moves_from_position(pos).into_iter().flatten() {
+            if walkable.get(next as usize) && !area.get(next as usize) {
+                area.set_bit(next as usize);
+                stack.push(next);
+            }
+        }
+    }
+    area
+}
+
+/// Returns an upper bound for the length of a path from pos through free.
+/// Paths alternate between the colors of a checker board, starting with the color that pos isn't.
+/// That doesn't hold across the edges of wrapped boards, where only the free cells are counted.
+fn path_length_bound<const S: usize, MODE: Mode>(board: &Bitboard<S, MODE>, pos: u16, free: MODE::Bitset) -> i16 {
+    if board.mode.wrap() {
+        return free.count_ones() as i16
+    }
+    let checkers = board.mode.checker_board_mask();
+    let (same, other) = if checkers.get(pos as usize) {
+        ((free & checkers).count_ones(), (free & !checkers).count_ones())
+    } else {
+        ((free & !checkers).count_ones(), (free & checkers).count_ones())
+    };
+    (2 * same.min(other) + (other > same) as u32) as i16
+}
+
+/// Looks the longest path from pos through free up in the cache, or searches it within the budget.
+fn cached_longest_path<const S: usize, MODE: Mode>(board: &Bitboard<S, MODE>, pos: u16, free: MODE::Bitset) -> Option<i16> {
+    let mut hasher = DefaultHasher::new();
+    (board.mode.w(), board.mode.h(), board.mode.wrap(), pos, free).hash(&mut hasher);
+    let key = hasher.finish();
+    let idx = key as usize % PATH_CACHE_SIZE;
+    if let Some((_, turns)) = PATH_CACHE.with(|cache| cache.borrow()[idx]).filter(|(entry_key, _)| *entry_key == key) {
+        return turns
+    }
+    let mut budget = SPACE_FILL_BUDGET;
+    let turns = longest_path(board, pos, free, &mut budget);
+    PATH_CACHE.with(|cache| cache.borrow_mut()[idx] = Some((key, turns)));
+    turns
+}
+
+/// Returns the length of the longest path from pos through free.
+/// Returns None if the search runs out of budget before it is certain.
+fn longest_path<const S: usize, MODE: Mode>(
+    board: &Bitboard<S, MODE>,
+    pos: u16,
+    mut free: MODE::Bitset,
+    budget: &mut usize,
+) -> Option<i16> {
+    if *budget == 0 {
+        return None
+    }
+    *budget -= 1;
+    // cells with few exits come first, like in Warnsdorff's rule, which finds long paths early
+    let exits = |pos: u16| board.mode.moves_from_position(pos).into_iter().flatten().filter(|x| free.get(*x as usize)).count();
+    let mut moves = ArrayVec::<u16, 4>::new();
+    moves.extend(board.mode.moves_from_position(pos).into_iter().flatten().filter(|x| free.get(*x as usize)));
+    moves.sort_by_key(|x| exits(*x));
+    let mut best = 0;
+    for next in moves {
+        free.unset_bit(next as usize);
+        // only search on if the path through next can beat the best one so far
+        if 1 + path_length_bound(board, next, free) > best {
+            best = best.max(1 + longest_path(board, next, free, budget)?);
+        }
+        free.set_bit(next as usize);
+        if best == path_length_bound(board, pos, free) {
+            break
+        }
+    }
+    Some(best)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test::Bencher;
+
+    fn create_board(you: &str) -> Bitboard<2, mode::Dynamic> {
+        // a is walled in on the left, b has one cell more on the right
+        let a = r###"{"id":"a","name":"","health":100,"body":[{"x":0,"y":0},{"x":0,"y":1}],"head":{"x":0,"y":0},"length":2,"shout":"","squad":""}"###;
+        let b = r###"{"id":"b","name":"","health":100,"body":[{"x":3,"y":0},{"x":2,"y":0},{"x":2,"y":1},{"x":2,"y":2}],"head":{"x":3,"y":0},"length":4,"shout":"","squad":""}"###;
+        let val = format!(
+            r###"{{"game":{{"id":"","ruleset":{{"name":"constrictor","version":"?","settings":{{}}}},"map":"standard","timeout":500,"source":""}},"turn":10,"board":{{"width":5,"height":3,"food":[],"hazards":[],"snakes":[{},{}]}},"you":{}}}"###,
+            a, b, if you == "a" { a } else { b },
+        );
+        Bitboard::<2, mode::Dynamic>::from_str(&val).unwrap()
+    }
+
+    #[test]
+    fn test_constrictor_solver() {
+        assert_eq!(constrictor_solver(&create_board("a")), Some(i16::MIN + 10 + 4));
+        assert_eq!(constrictor_solver(&create_board("b")), Some(i16::MAX - 10 - 4));
+
+        // once both can reach the same cell, there is no exact answer
+        let mut board = create_board("a");
+        board.bodies[0].unset_bit(2*5 + 2);
+        assert_eq!(constrictor_solver(&board), None);
+    }
+
+    /// Both snakes have half of an open 11x11 board to themselves.
+    fn create_open_board() -> Bitboard<2, mode::Standard> {
+        let wall: Vec<String> = (0..11).rev().map(|y| format!(r#"{{"x":5,"y":{}}}"#, y)).collect();
+        let a = format!(
+            r###"{{"id":"a","name":"","health":100,"body":[{{"x":4,"y":10}},{}],"head":{{"x":4,"y":10}},"length":12,"shout":"","squad":""}}"###,
+            wall.join(","),
+        );
+        let b = r###"{"id":"b","name":"","health":100,"body":[{"x":8,"y":5},{"x":8,"y":4},{"x":8,"y":3}],"head":{"x":8,"y":5},"length":3,"shout":"","squad":""}"###;
+        let val = format!(
+            r###"{{"game":{{"id":"","ruleset":{{"name":"constrictor","version":"?","settings":{{}}}},"map":"standard","timeout":500,"source":""}},"turn":60,"board":{{"width":11,"height":11,"food":[],"hazards":[],"snakes":[{},{}]}},"you":{}}}"###,
+            a, b, a,
+        );
+        Bitboard::<2, mode::Standard>::from_str(&val).unwrap()
+    }
+
+    #[test]
+    fn test_constrictor_solver_open_area() {
+        // a fills all 54 cells of its half, b can't quite fill its 52
+        let mut board = create_open_board();
+        assert_eq!(constrictor_solver(&board), Some(i16::MAX - 60 - 51));
+
+        // a single body part next to the wall makes b's path too expensive, which is remembered
+        board.bodies[0].set_bit(2*11 + 6);
+        assert_eq!(constrictor_solver(&board), None);
+        assert_eq!(constrictor_solver(&board), None);
+    }
+
+    #[bench]
+    fn bench_constrictor_solver(b: &mut Bencher) {
+        let board = create_board("a");
+        b.iter(|| {
+            constrictor_solver(&board)
+        })
+    }
+
+    /// The cost of a cache miss for a snake with half of the board to itself.
+    #[bench]
+    fn bench_longest_path_open_area(b: &mut Bencher) {
+        let board = create_open_board();
+        let area = reachable_area(&board, board.snakes[1].head, !board.bodies[0] & board.mode.full_board_mask());
+        b.iter(|| {
+            let mut budget = SPACE_FILL_BUDGET;
+            longest_path(&board, board.snakes[1].head, area, &mut budget)
+        })
+    }
+
+    /// The cost of a cache miss that uses up the whole budget.
+    #[bench]
+    fn bench_longest_path_out_of_budget(b: &mut Bencher) {
+        let mut board = create_open_board();
+        board.bodies[0].set_bit(2*11 + 6);
+        let area = reachable_area(&board, board.snakes[1].head, !board.bodies[0] & board.mode.full_board_mask());
+        b.iter(|| {
+            let mut budget = SPACE_FILL_BUDGET;
+            longest_path(&board, board.snakes[1].head, area, &mut budget)
+        })
+    }
+}
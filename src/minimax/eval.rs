@@ -56,7 +56,7 @@ pub fn eval_with_weights<const S: usize, MODE: Mode>(
         weights[13],weights[14],hazard_area_diff(board, &my_area, &enemy_area),
         weights[15],weights[16],area_diff::<MODE>(&my_close_area, &enemy_close_area),
         weights[17],weights[18],non_hazard_area_diff(board, &my_area, &enemy_area),
-        weights[19],weights[20],(MODE::W as Score - closest_food_distance),
+        weights[19],weights[20],(board.mode.w() as Score - closest_food_distance),
         weights[21],weights[22],controlled_tail_diff(board, &my_area, &enemy_area),
         weights[23],weights[24],(board.snakes[0].length%2) as Score,
     )
@@ -77,7 +77,7 @@ pub fn eval<const S: usize, MODE: Mode>(
                 turn_or_duel_progression(board, board.turn, 67, 250),
                 3,0,capped_length_diff(board, 5),
                 5,1,my_area_size - enemy_area_size,
-                3,0,(MODE::W as Score - food_dist),
+                3,0,(board.mode.w() as Score - food_dist),
                 27,29,controlled_tail_diff(board, &my_area, &enemy_area),
                 0,10,(board.snakes[0].length%2) as Score,
             )
@@ -109,16 +109,65 @@ pub fn eval<const S: usize, MODE: Mode>(
                 9,0,being_longer(board),
                 0,3,controlled_food_diff(board, &my_area, &enemy_area),
                 1,7,(my_area_size - enemy_area_size),
-                7,0,(MODE::W as Score - food_dist),
+                7,0,(board.mode.w() as Score - food_dist),
                 6,20,controlled_tail_diff(board, &my_area, &enemy_area),
             )
         },
-        Gamemode::Constrictor => {
+        Gamemode::StandardSnailMode => {
+            let me = board.snakes[0];
+            let ((my_area, enemy_area), _, food_dist) = area_control(board, 5);
+            score!(
+                turn_progression(board.turn, 0, 250),
+                2,2,me.health as Score,
+                -1,-1,lowest_enemy_health(board),
+                7,0,being_longer(board),
+                3,5,controlled_food_diff(board, &my_area, &enemy_area),
+                1,2,trail_free_area_diff(board, &my_area, &enemy_area),
+                7,0,(board.mode.w() as Score - food_dist),
+                6,20,controlled_tail_diff(board, &my_area, &enemy_area),
+            )
+        },
+        Gamemode::Solo => {
+            // there is nobody to beat, we just want to stay alive as long as possible
+            let me = board.snakes[0];
+            let ((my_area, _), _, food_dist) = area_control(board, 5);
+            score!(
+                turn_progression(board.turn, 0, 250),
+                2,2,me.health as Score,
+                5,5,checkered_area_size(board, &my_area),
+                3,3,(board.mode.w() as Score - food_dist),
+                20,20,my_area.get(me.tail as usize) as Score,
+            )
+        },
+        Gamemode::Constrictor | Gamemode::WrappedConstrictor => {
+            if let Some(score) = endgame::constrictor_solver(board) {
+                return score
+            }
             let ((my_area, enemy_area), (_, _), _) = area_control(board, 5);
             let (my_area_size, enemy_area_size) = (checkered_area_size(board, &my_area) as Score, checkered_area_size(board, &enemy_area) as Score);
             (my_area_size - enemy_area_size) as Score
         }
-        Gamemode::WrappedSpiral | Gamemode::WrappedWithHazard => {
+        Gamemode::StandardHealingPools => {
+            let me = board.snakes[0];
+            let ((my_area, enemy_area), _, closest_food_distance) = area_control(board, 5);
+            score!(
+                turn_progression(board.turn, 83, 250),
+                3,3,me.health as Score,
+                -1,-1,lowest_enemy_health(board),
+                7,0,being_longer(board),
+                7,5,controlled_food_diff(board, &my_area, &enemy_area),
+                5,5,hazard_area_diff(board, &my_area, &enemy_area),
+                4,7,area_diff::<MODE>(&my_area, &enemy_area),
+                10,6,(board.mode.w() as Score - closest_food_distance),
+                0,16,controlled_tail_diff(board, &my_area, &enemy_area),
+            )
+        },
+        Gamemode::WrappedSpiral
+        | Gamemode::WrappedWithHazard
+        | Gamemode::StandardRiversAndBridges
+        | Gamemode::StandardColumns
+        | Gamemode::StandardRings
+        | Gamemode::StandardScatterFill => {
             let me = board.snakes[0];
             let ((my_area, enemy_area), (my_close_area, enemy_close_area), closest_food_distance) = area_control(board, 5);
             score!(
@@ -128,7 +177,7 @@ pub fn eval<const S: usize, MODE: Mode>(
                 7,0,being_longer(board),
                 7,5,controlled_food_diff(board, &my_area, &enemy_area),
                 4,7,non_hazard_area_diff(board, &my_area, &enemy_area),
-                10,6,(MODE::W as Score - closest_food_distance),
+                10,6,(board.mode.w() as Score - closest_food_distance),
                 0,16,controlled_tail_diff(board, &my_area, &enemy_area),
             )
         }
@@ -142,7 +191,7 @@ pub fn eval<const S: usize, MODE: Mode>(
                 7,0,being_longer(board),
                 7,5,controlled_food_diff(board, &my_area, &enemy_area),
                 4,7,non_hazard_area_diff(board, &my_area, &enemy_area),
-                10,6,(MODE::W as Score - closest_food_distance),
+                10,6,(board.mode.w() as Score - closest_food_distance),
                 0,16,controlled_tail_diff(board, &my_area, &enemy_area),
             )
         },
@@ -151,14 +200,20 @@ pub fn eval<const S: usize, MODE: Mode>(
 
 pub fn eval_terminal<const S: usize, MODE: Mode>(board: &Bitboard<S, MODE>) -> Score {
     if board.snakes[0].is_dead() {
-        for snake in board.snakes[1..].iter() {
-            if snake.is_alive() {
-                return Score::MIN + board.turn as Score
-            }
+        // with allies still alive, the game is decided by them
+        let allies_alive = (1..S).any(|i| board.is_ally(0, i) && board.snakes[i].is_alive());
+        let enemies_alive = (1..S).any(|i| !board.is_ally(0, i) && board.snakes[i].is_alive());
+        match (allies_alive, enemies_alive) {
+            (false, true) => return Score::MIN + board.turn as Score,
+            (true, true) => return 0,
+            (true, false) => return Score::MAX - board.turn as Score,
+            (false, false) => (),
         }
         // draw value is different depending on gamemode
         return match board.gamemode {
-            Gamemode::Constrictor => 0,
+            Gamemode::Constrictor | Gamemode::WrappedConstrictor => 0,
+            // dying is the only way a solo game ends, the later the better
+            Gamemode::Solo => Score::MIN + board.turn as Score,
             _ => -10000 + board.turn as Score,
         }
     } else {
@@ -187,7 +242,7 @@ fn turn_or_duel_progression<const S: usize, MODE: Mode>(board: &Bitboard<S, MODE
 fn lowest_enemy_health<const S: usize, MODE: Mode>(board: &Bitboard<S, MODE>) -> Score {
     let mut lowest_enemy_health = 100;
     for i in 1..S {
-        if board.snakes[i].is_alive() {
+        if board.snakes[i].is_alive() && !board.is_ally(0, i) {
             if board.snakes[i].health < lowest_enemy_health {
                 lowest_enemy_health = board.snakes[i].health;
             }
@@ -199,7 +254,7 @@ fn lowest_enemy_health<const S: usize, MODE: Mode>(board: &Bitboard<S, MODE>) ->
 fn largest_enemy_length<const S: usize, MODE: Mode>(board: &Bitboard<S, MODE>) -> Score {
     let mut largest_enemy_length = 0;
     for i in 1..S {
-        if board.snakes[i].is_alive() {
+        if board.snakes[i].is_alive() && !board.is_ally(0, i) {
             if board.snakes[i].length > largest_enemy_length {
                 largest_enemy_length = board.snakes[i].length;
             }
@@ -209,24 +264,24 @@ fn largest_enemy_length<const S: usize, MODE: Mode>(board: &Bitboard<S, MODE>) -
 }
 
 fn length_diff<const S: usize, MODE: Mode>(board: &Bitboard<S, MODE>) -> Score {
-    MODE::W as Score * (board.snakes[0].length as Score - largest_enemy_length(board))
+    board.mode.w() as Score * (board.snakes[0].length as Score - largest_enemy_length(board))
 }
 
 fn capped_length_diff<const S: usize, MODE: Mode>(board: &Bitboard<S, MODE>, cap: Score) -> Score {
-    MODE::W as Score * (board.snakes[0].length as Score - largest_enemy_length(board)).min(cap)
+    board.mode.w() as Score * (board.snakes[0].length as Score - largest_enemy_length(board)).min(cap)
 }
 
 fn being_longer<const S: usize, MODE: Mode>(board: &Bitboard<S, MODE>) -> Score {
     let length_diff = length_diff(board);
     if length_diff > 0 {
-        (((length_diff + 1) as f64).log(1.5) * MODE::W as f64) as Score
+        (((length_diff + 1) as f64).log(1.5) * board.mode.w() as f64) as Score
     } else {
-        -((((-length_diff + 1) as f64).log(1.5) * MODE::W as f64) as Score)
+        -((((-length_diff + 1) as f64).log(1.5) * board.mode.w() as f64) as Score)
     }
 }
 
 fn distance_from_center<const S: usize, MODE: Mode>(board: &Bitboard<S, MODE>) -> Score {
-    board.distance(board.snakes[0].head, ((MODE::W/2)+(MODE::H/2)) as u16) as Score
+    board.distance(board.snakes[0].head, ((board.mode.w()/2)+(board.mode.h()/2)) as u16) as Score
 }
 
 fn controlled_food_diff<const S: usize, MODE: Mode>(
@@ -247,6 +302,19 @@ fn non_hazard_area_diff<const S: usize, MODE: Mode>(
     (*my_area & !board.hazard_mask).count_ones() as Score - (*enemy_area & !board.hazard_mask).count_ones() as Score
 }
 
+/// Snail trails decay by one hazard per turn, so every cell is worth the number of turns it can be
+/// entered without taking trail damage, out of the trail length.
+fn trail_free_area_diff<const S: usize, MODE: Mode>(
+    board: &Bitboard<S, MODE>, my_area: &MODE::Bitset, enemy_area: &MODE::Bitset
+) -> Score {
+    let mut diff = SNAIL_TRAIL_LENGTH as Score * area_diff::<MODE>(my_area, enemy_area);
+    for n in 1..=SNAIL_TRAIL_LENGTH {
+        let hazards = board.hazards_at_least(n);
+        diff -= (*my_area & hazards).count_ones() as Score - (*enemy_area & hazards).count_ones() as Score;
+    }
+    diff
+}
+
 fn area_diff<MODE: Mode>(my_area: &MODE::Bitset, enemy_area: &MODE::Bitset) -> Score {
     (*my_area).count_ones() as Score - (*enemy_area).count_ones() as Score
 }
@@ -254,8 +322,8 @@ fn area_diff<MODE: Mode>(my_area: &MODE::Bitset, enemy_area: &MODE::Bitset) -> S
 fn checkered_area_size<const S: usize, MODE: Mode>(
     board: &Bitboard<S, MODE>, area: &MODE::Bitset
 ) -> Score {
-    let x = (*area & MODE::CHECKER_BOARD_MASK).count_ones();
-    let y = (*area & !MODE::CHECKER_BOARD_MASK).count_ones();
+    let x = (*area & board.mode.checker_board_mask()).count_ones();
+    let y = (*area & !board.mode.checker_board_mask()).count_ones();
 
     let over = x.max(y) - x.min(y);
     (x + y - over + over.min(1)) as i16
@@ -284,13 +352,6 @@ fn controlled_tail_diff<const S: usize, MODE: Mode>(
     res
 }
 
-fn get_food_spawns(gamemode: Gamemode) -> &'static [usize] {
-    match gamemode {
-        Gamemode::WrappedArcadeMaze => &[20, 36, 104, 137, 147, 212, 218, 224, 327, 332, 337],
-        _ => &[],
-    }
-}
-
 // Returns ((my_fill, enemy_fill), (my_area, enemy_area), (my_close_area, enemy_close_area), my_distance_to_food)
 pub fn area_control<const S: usize, MODE: Mode>(
     board: &Bitboard<S, MODE>,
@@ -298,13 +359,11 @@ pub fn area_control<const S: usize, MODE: Mode>(
 ) -> ((MODE::Bitset, MODE::Bitset), (MODE::Bitset, MODE::Bitset), Score) {
     let mut state = (MODE::Bitset::with_bit_set(board.snakes[0].head as usize), MODE::Bitset::new());
     let mut close_area = state;
-    let walkable = if board.hazard_dmg > 95 {
-        !board.hazard_mask & !board.bodies[0] & MODE::FULL_BOARD_MASK
-    } else {
-        !board.bodies[0] & MODE::FULL_BOARD_MASK
-    };
-    for snake in &board.snakes[1..] {
-        if snake.is_alive() {
+    let walkable = !board.hazards_dealing(96) & !board.bodies[0] & board.mode.full_board_mask();
+    for (i, snake) in board.snakes.iter().enumerate().skip(1) {
+        if snake.is_alive() && board.is_ally(0, i) {
+            state.0.set_bit(snake.head as usize);
+        } else if snake.is_alive() {
             state.1.set_bit(snake.head as usize);
         }
     }
@@ -328,17 +387,17 @@ pub fn area_control<const S: usize, MODE: Mode>(
     loop {
         turn_counter += 1;
         debug_assert!(turn_counter < 10000, "endless loop in area_control\n{:?}\n{:?}", state, old_state);
-        let mut me = state.0 | (MODE::ALL_BUT_LEFT_EDGE_MASK & state.0)<<1 | (MODE::ALL_BUT_RIGHT_EDGE_MASK & state.0)>>1 | state.0<<MODE::W | state.0>>MODE::W;
-        let mut enemies = state.1 | (MODE::ALL_BUT_LEFT_EDGE_MASK & state.1)<<1 | (MODE::ALL_BUT_RIGHT_EDGE_MASK & state.1)>>1 | state.1<<MODE::W | state.1>>MODE::W;
-        if MODE::WRAP {
-            me |= (MODE::LEFT_EDGE_MASK & state.0) >> (MODE::W-1)
-                | (MODE::RIGHT_EDGE_MASK & state.0) << (MODE::W-1)
-                | (MODE::BOTTOM_EDGE_MASK & state.0) << ((MODE::H-1)*MODE::W)
-                | (MODE::TOP_EDGE_MASK & state.0) >> ((MODE::H-1)*MODE::W);
-            enemies |= (MODE::LEFT_EDGE_MASK & state.1) >> (MODE::W-1)
-                | (MODE::RIGHT_EDGE_MASK & state.1) << (MODE::W-1)
-                | (MODE::BOTTOM_EDGE_MASK & state.1) << ((MODE::H-1)*MODE::W)
-                | (MODE::TOP_EDGE_MASK & state.1) >> ((MODE::H-1)*MODE::W);
+        let mut me = state.0 | (board.mode.all_but_left_edge_mask() & state.0)<<1 | (board.mode.all_but_right_edge_mask() & state.0)>>1 | state.0<<board.mode.w() | state.0>>board.mode.w();
+        let mut enemies = state.1 | (board.mode.all_but_left_edge_mask() & state.1)<<1 | (board.mode.all_but_right_edge_mask() & state.1)>>1 | state.1<<board.mode.w() | state.1>>board.mode.w();
+        if board.mode.wrap() {
+            me |= (board.mode.left_edge_mask() & state.0) >> (board.mode.w()-1)
+                | (board.mode.right_edge_mask() & state.0) << (board.mode.w()-1)
+                | (board.mode.bottom_edge_mask() & state.0) << ((board.mode.h()-1)*board.mode.w())
+                | (board.mode.top_edge_mask() & state.0) >> ((board.mode.h()-1)*board.mode.w());
+            enemies |= (board.mode.left_edge_mask() & state.1) >> (board.mode.w()-1)
+                | (board.mode.right_edge_mask() & state.1) << (board.mode.w()-1)
+                | (board.mode.bottom_edge_mask() & state.1) << ((board.mode.h()-1)*board.mode.w())
+                | (board.mode.top_edge_mask() & state.1) >> ((board.mode.h()-1)*board.mode.w());
         }
         state = match longer {
             None => (state.0 | (walkable & (me & !enemies)), state.1 | (walkable & (enemies & !me))),
@@ -361,7 +420,7 @@ pub fn area_control<const S: usize, MODE: Mode>(
             if let Some(dist) = closest_food_distance {
                 return (state, close_area, dist as Score)
             } else {
-                return (state, close_area, MODE::W as Score)
+                return (state, close_area, board.mode.w() as Score)
             }
         } else {
             old_state = state;
@@ -373,22 +432,18 @@ pub fn flood_fill<const S: usize, MODE: Mode>(
     board: &Bitboard<S, MODE>,
 ) -> MODE::Bitset {
     let mut state = MODE::Bitset::with_bit_set(board.snakes[0].head as usize);
-    let walkable = if board.hazard_dmg > 95 {
-        !board.hazard_mask & !board.bodies[0] & MODE::FULL_BOARD_MASK
-    } else {
-        !board.bodies[0] & MODE::FULL_BOARD_MASK
-    };
+    let walkable = !board.hazards_dealing(96) & !board.bodies[0] & board.mode.full_board_mask();
     let mut turn_counter = 0;
 
     loop {
         turn_counter += 1;
         debug_assert!(turn_counter < 10000, "endless loop in area_control\n{:?}", state);
-        let mut next = state | (MODE::ALL_BUT_LEFT_EDGE_MASK & state)<<1 | (MODE::ALL_BUT_RIGHT_EDGE_MASK & state)>>1 | state<<MODE::W | state>>MODE::W;
-        if MODE::WRAP {
-            next |= (MODE::LEFT_EDGE_MASK & state) >> (MODE::W-1)
-                | (MODE::RIGHT_EDGE_MASK & state) << (MODE::W-1)
-                | (MODE::BOTTOM_EDGE_MASK & state) << ((MODE::H-1)*MODE::W)
-                | (MODE::TOP_EDGE_MASK & state) >> ((MODE::H-1)*MODE::W);
+        let mut next = state | (board.mode.all_but_left_edge_mask() & state)<<1 | (board.mode.all_but_right_edge_mask() & state)>>1 | state<<board.mode.w() | state>>board.mode.w();
+        if board.mode.wrap() {
+            next |= (board.mode.left_edge_mask() & state) >> (board.mode.w()-1)
+                | (board.mode.right_edge_mask() & state) << (board.mode.w()-1)
+                | (board.mode.bottom_edge_mask() & state) << ((board.mode.h()-1)*board.mode.w())
+                | (board.mode.top_edge_mask() & state) >> ((board.mode.h()-1)*board.mode.w());
         }
         next = next & walkable;
         if state == next {
@@ -400,11 +455,11 @@ pub fn flood_fill<const S: usize, MODE: Mode>(
 }
 
 #[allow(unused)]
-fn print_area_control<MODE: Mode>(me: MODE::Bitset, enemies: MODE::Bitset) {
+fn print_area_control<MODE: Mode>(mode: &MODE, me: MODE::Bitset, enemies: MODE::Bitset) {
     let mut debug = "".to_string();
-    for i in 0..MODE::H {
-        for j in 0..MODE::W {
-            debug.push_str(if me.get(MODE::W*(MODE::H-1-i)+j) { "x " } else if enemies.get(MODE::W*(MODE::H-1-i)+j) { "o " } else { ". " });
+    for i in 0..mode.h() {
+        for j in 0..mode.w() {
+            debug.push_str(if me.get(mode.w()*(mode.h()-1-i)+j) { "x " } else if enemies.get(mode.w()*(mode.h()-1-i)+j) { "o " } else { ". " });
         }
         debug.push_str("\n");
     }
@@ -2,54 +2,206 @@ use crate::bitboard::mode::Mode;
 use crate::bitboard::*;
 use crate::bitboard::move_gen::*;
 use crate::uct;
+use crate::config;
+use crate::engine::{self, Engine, Evaluation, SearchResult};
 
-use std::env;
 use std::time;
-use std::thread::{JoinHandle, spawn};
+use std::cell::RefCell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{JoinHandle, available_parallelism, spawn};
 use arrayvec::ArrayVec;
 use rand::seq::SliceRandom;
+use rand_pcg::Pcg64Mcg;
 use tracing::{info, debug};
 
 mod eval;
 mod endgame;
 mod ttable;
+pub mod ponder;
+pub mod watch;
 
-pub use ttable::init;
+pub use ttable::{init, clear as clear_ttable};
 
 lazy_static! {
-    static ref FIXED_DEPTH: i8 = if let Ok(var) = env::var("FIXED_DEPTH") {
-        var.parse().unwrap()
-    } else {
-        0
-    };
-    static ref FIXED_TIME: u64 = if let Ok(var) = env::var("FIXED_TIME") {
-        var.parse().unwrap()
-    } else {
-        0
+    /// The number of threads used by the parallel search, including the main thread.
+    /// Helper threads only help through the transposition table, so without one there are none.
+    static ref SEARCH_THREADS: usize = {
+        let config = &config::get().search;
+        match config.threads {
+            _ if !config.tt => 1,
+            0 => available_parallelism().map(|x| x.get()).unwrap_or(1),
+            threads => threads,
+        }
     };
+    /// Read once, because it's needed at every node.
+    static ref SAMPLE_FOOD_SPAWNS: bool = config::get().search.sample_food_spawns;
+    static ref QUIESCENCE: config::QuiescenceConfig = config::get().search.quiescence;
+}
+
+thread_local! {
+    /// Lets a search on another thread be stopped before its deadline.
+    static STOP_SIGNAL: RefCell<Option<Arc<AtomicBool>>> = const { RefCell::new(None) };
+}
+
+/// Makes searches on the current thread stop as soon as the signal is set.
+fn set_stop_signal(signal: Arc<AtomicBool>) {
+    STOP_SIGNAL.with(|x| *x.borrow_mut() = Some(signal));
+}
+
+/// Returns true if the current thread's stop signal is set.
+#[inline(always)]
+pub(crate) fn is_stopped() -> bool {
+    STOP_SIGNAL.with(|x| x.borrow().as_ref().is_some_and(|signal| signal.load(Ordering::Relaxed)))
 }
 
 pub type Score = i16;
 
-pub fn search<const S: usize, MODE: Mode>(board: &Bitboard<S, MODE>, mut deadline: time::SystemTime) -> (Move, Score, u8) {
-    if *FIXED_TIME > 0 {
-        deadline = time::SystemTime::now() + time::Duration::from_millis(*FIXED_TIME)
+/// The share of the time budget in percent that is held back for the MCTS fallback.
+const MCTS_FALLBACK_PERCENT: u32 = 25;
+
+/// Counters that a search thread keeps, summed up over all threads of a search.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stats {
+    pub nodes: u64,
+    /// The deepest ply that was reached, including search extensions and quiescence search.
+    pub seldepth: u8,
+    pub tt_probes: u64,
+    pub tt_hits: u64,
+}
+
+impl Stats {
+    fn merge(&mut self, other: Stats) {
+        self.nodes += other.nodes;
+        self.seldepth = self.seldepth.max(other.seldepth);
+        self.tt_probes += other.tt_probes;
+        self.tt_hits += other.tt_hits;
+    }
+
+    /// Counts a node at this ply, plies alternate between our moves and the enemies' moves.
+    #[inline(always)]
+    fn visit(&mut self, ply: u8) {
+        self.nodes += 1;
+        self.seldepth = self.seldepth.max(ply.div_ceil(2));
+    }
+
+    /// Counts a transposition table lookup.
+    #[inline(always)]
+    fn probe(&mut self, key: u64) -> Option<ttable::Entry> {
+        let entry = ttable::get(key);
+        self.tt_probes += 1;
+        self.tt_hits += entry.is_some() as u64;
+        entry
+    }
+
+    pub fn tt_hit_rate(&self) -> f64 {
+        if self.tt_probes == 0 {
+            0.0
+        } else {
+            self.tt_hits as f64 / self.tt_probes as f64
+        }
+    }
+}
+
+/// Best node search with iterative deepening, see `search`.
+pub struct Minimax;
+
+impl Engine for Minimax {
+    fn search<const S: usize, MODE: Mode>(&self, board: &Bitboard<S, MODE>, deadline: time::SystemTime) -> SearchResult<S> {
+        search(board, deadline)
+    }
+
+    fn ponder<const S: usize, MODE: Mode>(&self, board: &Bitboard<S, MODE>, mv: Move, deadline: time::SystemTime) {
+        ponder::start(board, mv, deadline);
     }
-    if *FIXED_DEPTH > 0 {
-        fixed_depth_search(board, *FIXED_DEPTH as u8)
+}
+
+pub fn search<const S: usize, MODE: Mode>(board: &Bitboard<S, MODE>, mut deadline: time::SystemTime) -> SearchResult<S> {
+    ttable::new_generation();
+    let config = &config::get().search;
+    if config.fixed_time_ms > 0 {
+        deadline = time::SystemTime::now() + time::Duration::from_millis(config.fixed_time_ms)
+    }
+    if config.fixed_depth > 0 {
+        fixed_depth_search(board, config.fixed_depth)
     } else {
-        #[cfg(not(feature = "parallel_search"))]
-        let (mv, score, depth) = best_node_search(board, deadline);
-        #[cfg(feature = "parallel_search")]
-        let (mv, score, depth) = parallel_best_node_search(board, deadline);
-
-        // if shallow loss is detected, return a result from MCTS instead
-        #[cfg(all(not(feature = "training"), feature = "mcts_fallback"))]
-        if S > 2 && score < Score::MIN + board.turn as Score + 6 {
-            let (mcts_mv, mcts_wr) = uct::search(board, deadline);
-            return (mcts_mv, score, depth)
+        // leave some time for MCTS, in case we need it
+        let fallback = config.mcts_fallback && S > 2 && !cfg!(feature = "training");
+        let minimax_deadline = if fallback {
+            let now = time::SystemTime::now();
+            now + deadline.duration_since(now).unwrap_or_default() * (100 - MCTS_FALLBACK_PERCENT) / 100
+        } else {
+            deadline
+        };
+        let result = if *SEARCH_THREADS > 1 {
+            lazy_smp_search(board, minimax_deadline)
+        } else {
+            best_node_search(board, minimax_deadline)
+        };
+
+        // if shallow loss is detected, return the result of MCTS instead, which its winrate marks
+        if let Evaluation::Score(score) = result.evaluation
+            && fallback && score < Score::MIN + board.turn as Score + 6
+        {
+            info!(
+                game.turn = board.turn,
+                game.mode = ?board.gamemode,
+                search.best_move = ?result.best_move,
+                search.score = score,
+                "mcts_fallback"
+            );
+            return uct::search(board, deadline)
+        }
+        result
+    }
+}
+
+/// Puts together the result of a finished search and looks up its principal variation.
+fn search_result<const S: usize, MODE: Mode>(
+    board: &Bitboard<S, MODE>,
+    best_move: Move,
+    evaluation: Evaluation,
+    depth: u8,
+    root_moves: Vec<(Move, Evaluation)>,
+    stats: Stats,
+    start_time: time::Instant,
+) -> SearchResult<S> {
+    SearchResult{
+        best_move,
+        evaluation,
+        depth,
+        seldepth: stats.seldepth,
+        nodes: stats.nodes,
+        nodes_per_second: engine::nodes_per_second(stats.nodes, start_time.elapsed()),
+        tt_hit_rate: stats.tt_hit_rate(),
+        pv: principal_variation(board, best_move, depth.max(1) as usize),
+        root_moves,
+    }
+}
+
+/// Follows the best moves in the transposition table, starting with us playing mv, for at most max_len turns.
+/// Enemy moves are guessed by move ordering where the table has no entry, but the line ends
+/// where the table doesn't know our next move.
+pub fn principal_variation<const S: usize, MODE: Mode>(board: &Bitboard<S, MODE>, mut mv: Move, max_len: usize) -> Vec<[Move; S]> {
+    let mut board = board.clone();
+    let history = vec![[0; 4]; board.mode.n()];
+    let mut pv = Vec::new();
+    loop {
+        let mut mvs = ttable::get(board.zobrist_key ^ zobrist::mv(mv))
+            .and_then(|entry| entry.get_best_moves::<S>())
+            .filter(|mvs| board.is_legal_enemy_moves(*mvs))
+            .unwrap_or_else(|| ordered_limited_move_combinations(&board, 1, &history).first().copied().unwrap_or([mv; S]));
+        mvs[0] = mv;
+        pv.push(mvs);
+        board.play_moves(&mvs);
+        sample_food_spawn(&mut board);
+        if pv.len() >= max_len || board.is_terminal() {
+            return pv
+        }
+        match ttable::get(board.zobrist_key).and_then(|entry| entry.get_best_moves::<1>()) {
+            Some([x]) if board.is_legal_move(board.snakes[0].head, x) => mv = x,
+            _ => return pv,
         }
-        (mv, score, depth)
     }
 }
 
@@ -57,74 +209,84 @@ pub fn search<const S: usize, MODE: Mode>(board: &Bitboard<S, MODE>, mut deadlin
 pub fn fixed_depth_search<const S: usize, MODE: Mode>(
     board: &Bitboard<S, MODE>,
     target_depth: u8
-) -> (Move, Score, u8) {
-    let mut node_counter = 0;
-    let mut history = vec![[0; 4]; MODE::N];
-    let my_moves = ordered_allowed_moves(board, 0, &history);
-    let mut enemy_moves = ordered_limited_move_combinations(board, 1, &history);
+) -> SearchResult<S> {
+    let mut board = board.clone();
+    let mut stats = Stats::default();
+    let mut history = vec![[0; 4]; board.mode.n()];
+    let my_moves = ordered_allowed_moves(&board, 0, &history);
+    let mut enemy_moves = ordered_limited_move_combinations(&board, 1, &history);
     let mut best_move = my_moves[0];
     let start_time = time::Instant::now(); // used to calculate nodes / second
     let deadline = time::SystemTime::now() + time::Duration::from_millis(500000);
     let mut best_score = Score::MIN+1;
+    let mut root_moves = vec![];
     for mv in &my_moves {
         let mut guess = 0;
         for depth in 1..=target_depth {
             let mut bounds = [Score::MIN, Score::MAX];
             while bounds[0] < bounds[1] {
                 let beta = guess + (guess == bounds[0]) as Score;
-                guess = alphabeta(&board, &mut node_counter, deadline, *mv, &mut enemy_moves, &mut history, depth, beta-1, beta).unwrap();
+                guess = alphabeta(&mut board, &mut stats, deadline, *mv, &mut enemy_moves, &mut history, depth, beta-1, beta).unwrap();
                 bounds[(guess < beta) as usize] = guess;
             }
         }
+        root_moves.push((*mv, Evaluation::Score(guess)));
         if guess > best_score {
             best_score = guess;
             best_move = *mv;
         }
     }
+    let result = search_result(&board, best_move, Evaluation::Score(best_score), target_depth, root_moves, stats, start_time);
     info!(
         game.turn = board.turn,
         game.mode = ?board.gamemode,
-        search.nodes_total = node_counter,
-        search.nodes_per_second = (node_counter as u128 * (time::Duration::from_secs(1).as_nanos() / start_time.elapsed().as_nanos())) as u64,
+        search.nodes_total = result.nodes,
+        search.nodes_per_second = result.nodes_per_second,
         search.best_move = ?best_move,
         search.score = best_score,
         search.depth = target_depth,
+        search.seldepth = result.seldepth,
+        search.tt_hit_rate = result.tt_hit_rate,
         search.time_used = time::Instant::now().duration_since(start_time).as_millis() as u64,
         "fixed_depth_search_finished"
     );
-    (best_move, best_score, target_depth)
+    result
 }
 
 /// An iterative deepening MTD(f)
 pub fn mtdf<const S: usize, MODE: Mode>(
     board: &Bitboard<S, MODE>,
     deadline: time::SystemTime
-) -> (Move, Score, u8) {
+) -> SearchResult<S> {
     let start_time = time::Instant::now(); // used to calculate nodes / second
-    let mut node_counter = 0;
+    let mut board = board.clone();
+    let mut stats = Stats::default();
     let mut rng = rand::thread_rng();
     let mut depth = 1;
-    let mut history = vec![[0; 4]; MODE::N];
-    let mut my_moves = allowed_moves(board, 0);
+    let mut history = vec![[0; 4]; board.mode.n()];
+    let mut my_moves = allowed_moves(&board, 0);
     my_moves.shuffle(&mut rng);
-    let mut enemy_moves = ordered_limited_move_combinations(board, 1, &history);
+    let mut enemy_moves = ordered_limited_move_combinations(&board, 1, &history);
     let mut best_move = my_moves[0];
     let mut best_score = Score::MIN+1;
+    let mut root_moves = vec![];
     'outer_loop: loop {
         let mut best_move_candidate = my_moves[0];
         let mut best_score_candidate = Score::MIN+1;
+        let mut root_move_candidates = vec![];
         for mv in &my_moves {
             let mut guess = 0;
             let mut bounds = [Score::MIN, Score::MAX];
             while bounds[0] < bounds[1] {
                 let beta = guess + (guess == bounds[0]) as Score;
-                if let Some(score) = alphabeta(&board, &mut node_counter, deadline, *mv, &mut enemy_moves, &mut history, depth, beta-1, beta) {
+                if let Some(score) = alphabeta(&mut board, &mut stats, deadline, *mv, &mut enemy_moves, &mut history, depth, beta-1, beta) {
                     guess = score;
                     bounds[(guess < beta) as usize] = guess;
                 } else {
                     break 'outer_loop
                 }
             }
+            root_move_candidates.push((*mv, Evaluation::Score(guess)));
             if guess > best_score_candidate {
                 best_score_candidate = guess;
                 best_move_candidate = *mv;
@@ -132,23 +294,27 @@ pub fn mtdf<const S: usize, MODE: Mode>(
         }
         best_score = best_score_candidate;
         best_move = best_move_candidate;
+        root_moves = root_move_candidates;
         if best_score > Score::MAX-1000 || best_score < Score::MIN+1000 || depth == u8::MAX {
             break // Our last best move resulted in a terminal state, so we don't need to search deeper
         }
         depth += 1;
     }
+    let result = search_result(&board, best_move, Evaluation::Score(best_score), depth, root_moves, stats, start_time);
     info!(
         game.turn = board.turn,
         game.mode = ?board.gamemode,
-        search.nodes_total = node_counter,
-        search.nodes_per_second = (node_counter as u128 * (time::Duration::from_secs(1).as_nanos() / start_time.elapsed().as_nanos())) as u64,
+        search.nodes_total = result.nodes,
+        search.nodes_per_second = result.nodes_per_second,
         search.best_move = ?best_move,
         search.score = best_score,
         search.depth = depth,
+        search.seldepth = result.seldepth,
+        search.tt_hit_rate = result.tt_hit_rate,
         search.time_used = time::Instant::now().duration_since(start_time).as_millis() as u64,
-        "fixed_depth_search_finished"
+        "mtdf_search_finished"
     );
-    (best_move, best_score, depth)
+    result
 }
 
 fn next_bns_guess(prev_guess: Score, alpha: Score, beta: Score) -> Score {
@@ -165,154 +331,143 @@ fn next_bns_guess(prev_guess: Score, alpha: Score, beta: Score) -> Score {
     }
 }
 
-pub fn parallel_best_node_search<const S: usize, MODE: Mode>(
+/// Returns the only move without searching, if we have just one.
+fn only_move<const S: usize, MODE: Mode>(board: &Bitboard<S, MODE>) -> Option<SearchResult<S>> {
+    let my_allowed_moves = allowed_moves(board, 0);
+    if my_allowed_moves.len() != 1 {
+        return None
+    }
+    debug!(
+        game.turn = board.turn,
+        game.mode = ?board.gamemode,
+        search.best_move = ?my_allowed_moves[0],
+        "returned_only_move"
+    );
+    let root_moves = vec![(my_allowed_moves[0], Evaluation::Score(0))];
+    Some(search_result(board, my_allowed_moves[0], Evaluation::Score(0), 0, root_moves, Stats::default(), time::Instant::now()))
+}
+
+/// A Lazy SMP search: helper threads search the same root as the main thread, sharing the
+/// transposition table. Helpers start at different depths and use different root move orders,
+/// so they fill the table with results the main thread can use.
+pub fn lazy_smp_search<const S: usize, MODE: Mode>(
     board: &Bitboard<S, MODE>,
     deadline: time::SystemTime
-) -> (Move, Score, u8) {
-    let mut rng = rand::thread_rng();
+) -> SearchResult<S> {
     let start_time = time::Instant::now();
-    let mut node_counter = 0;
-    let mut history = vec![[0; 4]; MODE::N];
+    let mut stats = Stats::default();
 
-    let board = board.clone();
-    let mut enemy_moves = ordered_limited_move_combinations(&board, 1, &history);
-    let mut my_allowed_moves = allowed_moves(&board, 0);
-    my_allowed_moves.shuffle(&mut rng);
-    if my_allowed_moves.len() == 1 {
-        debug!(
-            game.turn = board.turn,
-            game.mode = ?board.gamemode,
-            search.best_move = ?my_allowed_moves[0],
-            "returned_only_move"
-        );
-        return (my_allowed_moves[0], 0, 0)
+    if let Some(result) = only_move(board) {
+        return result
     }
 
-    let mut best_move = my_allowed_moves[0];
-    let mut best_score = Score::MIN+1;
-    let mut depth = 1;
+    let stop = Arc::new(AtomicBool::new(false));
+    let helpers: Vec<JoinHandle<Stats>> = (1..*SEARCH_THREADS).map(|i| {
+        let b = board.clone();
+        let stop = stop.clone();
+        spawn(move || {
+            set_stop_signal(stop);
+            let mut stats = Stats::default();
+            iterative_deepening(&b, deadline, 1 + (i % 2) as u8, &mut stats);
+            stats
+        })
+    }).collect();
 
-    let mut last_test = 0;
-    'outer_loop: loop {
-        let mut my_moves = my_allowed_moves.clone();
-        let mut alpha = Score::MIN;
-        let mut beta = Score::MAX;
-        loop {
-            let test = next_bns_guess(last_test, alpha, beta);
-            let mut better_moves = ArrayVec::<Move, 4>::new();
-            if depth > 3 && my_moves.len() > 1 {
-                let mut join_handles: ArrayVec<(moves::Move, JoinHandle<(Option<i16>, Vec<[u64; 4]>, u64)>), 4> = ArrayVec::new();
-                for mv in &my_moves {
-                    let b = board.clone();
-                    let mut node_counter = 0;
-                    let m = mv.clone();
-                    let mut em = enemy_moves.clone();
-                    let mut hist = history.clone();
-                    join_handles.push((*mv, spawn(move || {
-                        let score = alphabeta(&b, &mut node_counter, deadline, m, &mut em, &mut hist, depth, test-1, test);
-                        (score, hist, node_counter)
-                    })));
-                }
-                let old_hist = history.clone();
-                for (mv, handle) in join_handles {
-                    if let Ok((Some(score), hist, nc)) = handle.join() {
-                        node_counter += nc;
-                        for i in 0..(MODE::W*MODE::H) {
-                            for j in 0..4 {
-                                history[i][j] += hist[i][j] - old_hist[i][j];
-                            }
-                        }
-                        if score >= test {
-                            better_moves.push(mv);
-                        }
-                    } else {
-                        depth -= 1;
-                        break 'outer_loop // time has run out
-                    }
-                }
-            } else {
-                for mv in &my_moves {
-                    if let Some(score) = alphabeta(&board, &mut node_counter, deadline, *mv, &mut enemy_moves, &mut history, depth, test-1, test) {
-                        if score >= test {
-                            better_moves.push(*mv);
-                        }
-                    } else {
-                        depth -= 1;
-                        break 'outer_loop // time has run out
-                    }
-                }
-            }
-            if better_moves.len() == 0 {
-                beta = test; // update beta
-            } else {
-                alpha = test; // update alpha
-                my_moves = better_moves; // update subtrees left to search
-            }
-            if (beta as i32 - alpha as i32) < 2 || my_moves.len() == 1 {
-                last_test = test;
-                best_score = test;
-                best_move = my_moves[0];
-                break
-            }
-        }
-        if best_score > Score::MAX-1000 || best_score < Score::MIN+1000 || depth == u8::MAX-1 {
-            break // Our last best move resulted in a terminal state, so we don't need to search deeper
-        }
-        depth += 1;
+    let (best_move, best_score, depth, root_moves) = iterative_deepening(board, deadline, 1, &mut stats);
+    stop.store(true, Ordering::Relaxed);
+    for handle in helpers {
+        stats.merge(handle.join().unwrap_or_default());
     }
+
+    let result = search_result(board, best_move, Evaluation::Score(best_score), depth, root_moves, stats, start_time);
     info!(
         game.turn = board.turn,
         game.mode = ?board.gamemode,
-        search.nodes_total = node_counter,
-        search.nodes_per_second = (node_counter as u128 * (time::Duration::from_secs(1).as_nanos() / start_time.elapsed().as_nanos())) as u64,
+        search.nodes_total = result.nodes,
+        search.nodes_per_second = result.nodes_per_second,
         search.best_move = ?best_move,
         search.score = best_score,
         search.depth = depth,
+        search.seldepth = result.seldepth,
+        search.tt_hit_rate = result.tt_hit_rate,
+        search.threads = *SEARCH_THREADS,
         search.time_used = time::Instant::now().duration_since(start_time).as_millis() as u64,
         "search_finished"
     );
-    (best_move, best_score, depth)
+    result
 }
 
 pub fn best_node_search<const S: usize, MODE: Mode>(
     board: &Bitboard<S, MODE>,
     deadline: time::SystemTime
-) -> (Move, Score, u8) {
-    let mut rng = rand::thread_rng();
+) -> SearchResult<S> {
     let start_time = time::Instant::now();
-    let mut node_counter = 0;
-    let mut history = vec![[0; 4]; MODE::N];
+    let mut stats = Stats::default();
+
+    if let Some(result) = only_move(board) {
+        return result
+    }
+
+    let (best_move, best_score, depth, root_moves) = iterative_deepening(board, deadline, 1, &mut stats);
+    let result = search_result(board, best_move, Evaluation::Score(best_score), depth, root_moves, stats, start_time);
+    info!(
+        game.turn = board.turn,
+        game.mode = ?board.gamemode,
+        search.nodes_total = result.nodes,
+        search.nodes_per_second = result.nodes_per_second,
+        search.best_move = ?best_move,
+        search.score = best_score,
+        search.depth = depth,
+        search.seldepth = result.seldepth,
+        search.tt_hit_rate = result.tt_hit_rate,
+        search.time_used = time::Instant::now().duration_since(start_time).as_millis() as u64,
+        "search_finished"
+    );
+    result
+}
+
+/// An iterative deepening best node search, starting at first_depth.
+/// Root moves are searched in random order.
+/// Also returns the bounds on each root move's score that the last finished iteration established.
+fn iterative_deepening<const S: usize, MODE: Mode>(
+    board: &Bitboard<S, MODE>,
+    deadline: time::SystemTime,
+    first_depth: u8,
+    stats: &mut Stats
+) -> (Move, Score, u8, Vec<(Move, Evaluation)>) {
+    let mut board = board.clone();
+    let mut rng = rand::thread_rng();
+    let mut history = vec![[0; 4]; board.mode.n()];
 
-    let board = board.clone();
     let mut enemy_moves = ordered_limited_move_combinations(&board, 1, &history);
     let mut my_allowed_moves = allowed_moves(&board, 0);
     my_allowed_moves.shuffle(&mut rng);
-    if my_allowed_moves.len() == 1 {
-        debug!(
-            game.turn = board.turn,
-            game.mode = ?board.gamemode,
-            search.best_move = ?my_allowed_moves[0],
-            "returned_only_move"
-        );
-        return (my_allowed_moves[0], 0, 0)
-    }
 
     let mut best_move = my_allowed_moves[0];
     let mut best_score = Score::MIN+1;
-    let mut depth = 1;
+    let mut depth = first_depth;
+    let mut root_moves = vec![];
 
     let mut last_test = 0;
     'outer_loop: loop {
         let mut my_moves = my_allowed_moves.clone();
         let mut alpha = Score::MIN;
         let mut beta = Score::MAX;
+        let mut root_move_bounds = Vec::<(Move, Evaluation)>::with_capacity(4);
         loop {
             let test = next_bns_guess(last_test, alpha, beta);
             let mut better_moves = ArrayVec::<Move, 4>::new();
             for mv in &my_moves {
-                if let Some(score) = alphabeta(&board, &mut node_counter, deadline, *mv, &mut enemy_moves, &mut history, depth, test-1, test) {
-                    if score >= test {
+                if let Some(score) = alphabeta(&mut board, stats, deadline, *mv, &mut enemy_moves, &mut history, depth, test-1, test) {
+                    let bound = if score >= test {
                         better_moves.push(*mv);
+                        Evaluation::LowerBound(score)
+                    } else {
+                        Evaluation::UpperBound(score)
+                    };
+                    match root_move_bounds.iter_mut().find(|x| x.0 == *mv) {
+                        Some(x) => x.1 = bound,
+                        None => root_move_bounds.push((*mv, bound)),
                     }
                 } else {
                     depth -= 1;
@@ -332,28 +487,19 @@ pub fn best_node_search<const S: usize, MODE: Mode>(
                 break
             }
         }
+        root_moves = root_move_bounds;
+        watch::report(best_move);
         if best_score > Score::MAX-1000 || best_score < Score::MIN+1000 || depth == u8::MAX-1 {
             break // Our last best move resulted in a terminal state, so we don't need to search deeper
         }
         depth += 1;
     }
-    info!(
-        game.turn = board.turn,
-        game.mode = ?board.gamemode,
-        search.nodes_total = node_counter,
-        search.nodes_per_second = (node_counter as u128 * (time::Duration::from_secs(1).as_nanos() / start_time.elapsed().as_nanos())) as u64,
-        search.best_move = ?best_move,
-        search.score = best_score,
-        search.depth = depth,
-        search.time_used = time::Instant::now().duration_since(start_time).as_millis() as u64,
-        "search_finished"
-    );
-    (best_move, best_score, depth)
+    (best_move, best_score, depth, root_moves)
 }
 
 pub fn alphabeta<const S: usize, MODE: Mode>(
-    board: &Bitboard<S, MODE>,
-    node_counter: &mut u64,
+    board: &mut Bitboard<S, MODE>,
+    stats: &mut Stats,
     deadline: time::SystemTime,
     mv: Move,
     enemy_moves: &mut ArrayVec<[Move; S], 4>,
@@ -363,12 +509,12 @@ pub fn alphabeta<const S: usize, MODE: Mode>(
     alpha: Score,
     beta: Score
 ) -> Option<Score> {  // min call
-    ab_min(board, node_counter, deadline, mv, enemy_moves, history, depth, 0, alpha, beta)
+    ab_min(board, stats, deadline, mv, enemy_moves, history, depth, 0, alpha, beta)
 }
 
 pub fn ab_min<const S: usize, MODE: Mode>(
-    board: &Bitboard<S, MODE>,
-    node_counter: &mut u64,
+    board: &mut Bitboard<S, MODE>,
+    stats: &mut Stats,
     deadline: time::SystemTime,
     mv: Move,
     enemy_moves: &mut ArrayVec<[Move; S], 4>,
@@ -378,13 +524,13 @@ pub fn ab_min<const S: usize, MODE: Mode>(
     mut alpha: Score,
     mut beta: Score
 ) -> Option<Score> {  // min call
-    if time::SystemTime::now() > deadline {
+    if time::SystemTime::now() > deadline || is_stopped() {
         return None
     }
     let mut tt_move = None;
-    let tt_key = ttable::hash(&(board, mv));
+    let tt_key = board.zobrist_key ^ zobrist::mv(mv);
     if enemy_moves.len() > 1 {
-        let tt_entry = ttable::get(tt_key);
+        let tt_entry = stats.probe(tt_key);
         if let Some(entry) = tt_entry {
             if entry.get_depth() >= depth {
                 let tt_score = entry.get_score();
@@ -419,7 +565,7 @@ pub fn ab_min<const S: usize, MODE: Mode>(
         } else {
             seen_moves.push(mvs.clone());
         }
-        let score = ab_max(board, node_counter, deadline, &mvs, history, depth, ply+1, alpha, beta)?;
+        let score = ab_max(board, stats, deadline, &mvs, history, depth, ply+1, alpha, beta)?;
         if score < alpha {
             best_score = score;
             best_moves = *mvs;
@@ -444,32 +590,57 @@ pub fn ab_min<const S: usize, MODE: Mode>(
 }
 
 pub fn ab_max<const S: usize, MODE: Mode>(
-    board: &Bitboard<S, MODE>,
-    node_counter: &mut u64,
+    board: &mut Bitboard<S, MODE>,
+    stats: &mut Stats,
     deadline: time::SystemTime,
     moves: &[Move; S],
     history: &mut [[u64; 4]],
+    depth: u8,
+    ply: u8,
+    alpha: Score,
+    beta: Score
+) -> Option<Score> {
+    let undo = board.make_moves(moves);
+    sample_food_spawn(board);
+    stats.visit(ply);
+    let score = ab_max_made(board, stats, deadline, history, depth, ply, alpha, beta);
+    board.unmake_moves(undo);
+    score
+}
+
+/// Spawns food on a board in the search tree, if food spawns are sampled.
+/// The RNG is seeded with the position's key, so a position always gets the same spawn and
+/// transpositions stay consistent.
+#[inline(always)]
+fn sample_food_spawn<const S: usize, MODE: Mode>(board: &mut Bitboard<S, MODE>) {
+    if *SAMPLE_FOOD_SPAWNS {
+        board.spawn_food(&mut Pcg64Mcg::new(board.zobrist_key as u128));
+    }
+}
+
+/// The max call on a board that the joint move has already been made on.
+fn ab_max_made<const S: usize, MODE: Mode>(
+    board: &mut Bitboard<S, MODE>,
+    stats: &mut Stats,
+    deadline: time::SystemTime,
+    history: &mut [[u64; 4]],
     mut depth: u8,
     ply: u8,
     mut alpha: Score,
     mut beta: Score
 ) -> Option<Score> {
-    let mut child = board.clone();
-    (child.apply_moves.clone())(&mut child, moves);
-    *node_counter += 1;
-
     // search stops
-    if child.is_terminal() {
-        return Some(eval::eval_terminal(&child))
-    } else if depth == 1 && (get_quiescence_params(board.gamemode).1)(&child) { // calls is_stable
-        return Some(eval::eval(&child))
+    if board.is_terminal() {
+        return Some(eval::eval_terminal(board))
+    } else if depth == 1 && (get_quiescence_params(board.gamemode).1)(board) { // calls is_stable
+        return Some(eval::eval(board))
     }
     // check TT
-    let tt_key = ttable::hash(&child);
+    let tt_key = board.zobrist_key;
     let mut tt_move = None;
-    let my_moves = ordered_allowed_moves(&child, 0, history);
+    let my_moves = ordered_allowed_moves(board, 0, history);
     if my_moves.len() > 1 {
-        let tt_entry = ttable::get(tt_key);
+        let tt_entry = stats.probe(tt_key);
         if let Some(entry) = tt_entry {
             if entry.get_depth() >= depth {
                 let tt_score = entry.get_score();
@@ -497,7 +668,7 @@ pub fn ab_max<const S: usize, MODE: Mode>(
     let mut best_score = Score::MIN;
     let mut best_move = Move::Left;
     let mut seen_moves = ArrayVec::<Move, 4>::default();
-    let mut next_enemy_moves = ordered_limited_move_combinations(&child, 1, history);
+    let mut next_enemy_moves = ordered_limited_move_combinations(board, 1, history);
 
     // search extension for forcing sequences
     if my_moves.len() * next_enemy_moves.len() <= 1 {
@@ -512,9 +683,9 @@ pub fn ab_max<const S: usize, MODE: Mode>(
         }
         let score = if depth == 1 {
             let (q_depth, is_stable) = get_quiescence_params(board.gamemode);
-            quiescence(&child, node_counter, deadline, is_stable, *mv, &mut next_enemy_moves, history, q_depth, alpha, beta)?
+            quiescence(board, stats, deadline, is_stable, *mv, &mut next_enemy_moves, history, q_depth, ply+1, alpha, beta)?
         } else {
-            ab_min(&child, node_counter, deadline, *mv, &mut next_enemy_moves, history, depth-1, ply+1, alpha, beta)?
+            ab_min(board, stats, deadline, *mv, &mut next_enemy_moves, history, depth-1, ply+1, alpha, beta)?
         };
         if score > beta {
             best_score = score;
@@ -541,8 +712,9 @@ pub fn ab_max<const S: usize, MODE: Mode>(
 fn get_quiescence_params<const S: usize, MODE: Mode>(
     mode: Gamemode
 ) -> (u8, fn(&Bitboard<S, MODE>) -> bool) {
+    let depths = *QUIESCENCE;
     match mode {
-        Gamemode::Standard | Gamemode::WrappedIslandsBridges => (5, |board| {
+        Gamemode::Standard | Gamemode::WrappedIslandsBridges => (depths.standard, |board| {
             for i in 1..S {
                 let snake = board.snakes[i];
                 if snake.is_alive() && board.distance(board.snakes[0].head, snake.head) < 3 {
@@ -551,7 +723,33 @@ fn get_quiescence_params<const S: usize, MODE: Mode>(
             }
             true
         }),
-        Gamemode::WrappedArcadeMaze => (20, |board| {
+        Gamemode::Squad => (depths.standard, |board| {
+            // allies are not a threat to our head
+            for i in 1..S {
+                let snake = board.snakes[i];
+                if snake.is_alive() && !board.is_ally(0, i) && board.distance(board.snakes[0].head, snake.head) < 3 {
+                    return false
+                }
+            }
+            true
+        }),
+        Gamemode::StandardSnailMode => (depths.snail_mode, |board| {
+            // trails next to our head may be about to kill us
+            let me = board.snakes[0];
+            for pos in board.mode.moves_from_position(me.head).into_iter().flatten() {
+                if board.hazard_mask.get(pos as usize) && board.hazard_damage(pos as usize) >= me.health as i16 {
+                    return false
+                }
+            }
+            for i in 1..S {
+                let snake = board.snakes[i];
+                if snake.is_alive() && board.distance(me.head, snake.head) < 3 {
+                    return false
+                }
+            }
+            true
+        }),
+        Gamemode::WrappedArcadeMaze => (depths.arcade_maze, |board| {
             let mut moves = 1;
             for (i, snake) in board.snakes.iter().enumerate() {
                 if snake.is_dead() {
@@ -561,7 +759,12 @@ fn get_quiescence_params<const S: usize, MODE: Mode>(
             }
             moves > 2
         }),
-        Gamemode::WrappedSpiral | Gamemode::WrappedWithHazard => (3, |board| {
+        Gamemode::WrappedSpiral
+        | Gamemode::WrappedWithHazard
+        | Gamemode::StandardRiversAndBridges
+        | Gamemode::StandardColumns
+        | Gamemode::StandardRings
+        | Gamemode::StandardScatterFill => (depths.hazards, |board| {
             for snake in board.snakes {
                 if snake.is_dead() {
                     continue
@@ -570,7 +773,7 @@ fn get_quiescence_params<const S: usize, MODE: Mode>(
                     return false
                 }
                 for i in 0..4 {
-                    if let Some(pos) = MODE::moves_from_position(snake.head)[i] {
+                    if let Some(pos) = board.mode.moves_from_position(snake.head)[i] {
                         if board.food.get(pos as usize) {
                             return false
                         }
@@ -585,62 +788,27 @@ fn get_quiescence_params<const S: usize, MODE: Mode>(
 
 /// Returns None if it received a timeout from stop_receiver.
 pub fn quiescence<const S: usize, MODE: Mode>(
-    board: &Bitboard<S, MODE>,
-    node_counter: &mut u64,
+    board: &mut Bitboard<S, MODE>,
+    stats: &mut Stats,
     deadline: time::SystemTime,
     is_stable: fn (&Bitboard<S, MODE>) -> bool,
     mv: Move,
     enemy_moves: &mut ArrayVec<[Move; S], 4>,
     history: &mut [[u64; 4]],
     depth: u8,
+    ply: u8,
     alpha: Score,
     mut beta: Score
 ) -> Option<Score> {  // min call
-    if time::SystemTime::now() > deadline {
+    if time::SystemTime::now() > deadline || is_stopped() {
         return None
     }
 
     let mut best_score = Score::MAX;
     let mut best_moves = [Move::Up; S];
     for mvs in enemy_moves.iter_mut() {
-        let score = 'max_call: { // max call
-            let mut ialpha = alpha;
-            let ibeta = beta;
-            let mut ibest_score = Score::MIN;
-            let mut ibest_move = Move::Up;
-            mvs[0] = mv;
-            let mut child = board.clone();
-            (child.apply_moves.clone())(&mut child, &mvs);
-            *node_counter += 1;
-
-            // search stops
-            if child.is_terminal() {
-                break 'max_call eval::eval_terminal(&child);
-            } else if depth == 1 || is_stable(&child) {
-                break 'max_call eval::eval(&child);
-            }
-
-            // continue search
-            let mut next_enemy_moves = ordered_limited_move_combinations(&child, 1, history);
-            for mv in &ordered_allowed_moves(&child, 0, history) {
-                let iscore = quiescence(&child, node_counter, deadline, is_stable, *mv, &mut next_enemy_moves, history, depth-1, ialpha, ibeta)?;
-                if iscore > ibeta {
-                    ibest_score = iscore;
-                    ibest_move = *mv;
-                    break;
-                }
-                if iscore > ibest_score {
-                    ibest_score = iscore;
-                    ibest_move = *mv;
-                    if iscore > ialpha {
-                        ialpha = iscore;
-                    }
-                }
-            }
-            // update history heuristic
-            history[board.snakes[0].head as usize][ibest_move.to_int() as usize] += depth as u64;
-            ibest_score
-        };
+        mvs[0] = mv;
+        let score = quiescence_max(board, stats, deadline, is_stable, mvs, history, depth, ply+1, alpha, beta)?;
         if score < alpha {
             best_score = score;
             best_moves = *mvs;
@@ -662,3 +830,67 @@ pub fn quiescence<const S: usize, MODE: Mode>(
     }
     Some(best_score)
 }
+
+/// The max call of quiescence search.
+fn quiescence_max<const S: usize, MODE: Mode>(
+    board: &mut Bitboard<S, MODE>,
+    stats: &mut Stats,
+    deadline: time::SystemTime,
+    is_stable: fn (&Bitboard<S, MODE>) -> bool,
+    moves: &[Move; S],
+    history: &mut [[u64; 4]],
+    depth: u8,
+    ply: u8,
+    alpha: Score,
+    beta: Score
+) -> Option<Score> {
+    let undo = board.make_moves(moves);
+    sample_food_spawn(board);
+    stats.visit(ply);
+    let score = quiescence_max_made(board, stats, deadline, is_stable, history, depth, ply, alpha, beta);
+    board.unmake_moves(undo);
+    score
+}
+
+/// The max call of quiescence search on a board that the joint move has already been made on.
+fn quiescence_max_made<const S: usize, MODE: Mode>(
+    board: &mut Bitboard<S, MODE>,
+    stats: &mut Stats,
+    deadline: time::SystemTime,
+    is_stable: fn (&Bitboard<S, MODE>) -> bool,
+    history: &mut [[u64; 4]],
+    depth: u8,
+    ply: u8,
+    mut alpha: Score,
+    beta: Score
+) -> Option<Score> {
+    // search stops
+    if board.is_terminal() {
+        return Some(eval::eval_terminal(board))
+    } else if depth == 1 || is_stable(board) {
+        return Some(eval::eval(board))
+    }
+
+    // continue search
+    let mut best_score = Score::MIN;
+    let mut best_move = Move::Up;
+    let mut next_enemy_moves = ordered_limited_move_combinations(board, 1, history);
+    for mv in &ordered_allowed_moves(board, 0, history) {
+        let score = quiescence(board, stats, deadline, is_stable, *mv, &mut next_enemy_moves, history, depth-1, ply+1, alpha, beta)?;
+        if score > beta {
+            best_score = score;
+            best_move = *mv;
+            break;
+        }
+        if score > best_score {
+            best_score = score;
+            best_move = *mv;
+            if score > alpha {
+                alpha = score;
+            }
+        }
+    }
+    // update history heuristic
+    history[board.snakes[0].head as usize][best_move.to_int() as usize] += depth as u64;
+    Some(best_score)
+}
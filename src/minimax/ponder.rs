@@ -0,0 +1,94 @@
+use crate::bitboard::mode::Mode;
+use crate::bitboard::*;
+
+use crate::config;
+
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{JoinHandle, spawn};
+use std::time;
+use tracing::debug;
+
+use super::{best_node_search, principal_variation, set_stop_signal};
+
+lazy_static! {
+    /// There is only ever one ponder search, so it can't compete with another one for the CPU.
+    static ref PONDER_THREAD: Mutex<Option<(JoinHandle<()>, Arc<AtomicBool>)>> = Mutex::new(None);
+}
+
+/// Returns true if pondering should be done with this many games running.
+pub fn is_enabled(active_games: usize) -> bool {
+    config::get().search.tt && active_games <= config::get().search.ponder_max_games
+}
+
+/// Stops the running ponder search, if any, and waits for it to finish.
+pub fn stop() {
+    join(&mut PONDER_THREAD.lock().unwrap());
+}
+
+fn join(thread: &mut Option<(JoinHandle<()>, Arc<AtomicBool>)>) {
+    if let Some((handle, stop)) = thread.take() {
+        stop.store(true, Ordering::Relaxed);
+        handle.join().ok();
+        debug!("ponder_stopped");
+    }
+}
+
+/// Starts searching the position we expect to see next, after we played mv on board.
+/// The results are written into the transposition table, where the next search can pick them up.
+/// Enemy moves are taken from the principal variation if possible.
+pub fn start<const S: usize, MODE: Mode>(board: &Bitboard<S, MODE>, mv: Move, deadline: time::SystemTime) {
+    let mut thread = PONDER_THREAD.lock().unwrap();
+    join(&mut thread);
+
+    let mvs = predict_moves(board, mv);
+    let mut child = board.clone();
+    child.play_moves(&mvs);
+    if child.is_terminal() {
+        return
+    }
+    debug!(
+        game.turn = child.turn,
+        ponder.moves = ?mvs,
+        "ponder_started"
+    );
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let signal = stop.clone();
+    let handle = spawn(move || {
+        set_stop_signal(signal);
+        best_node_search(&child, deadline);
+    });
+    *thread = Some((handle, stop));
+}
+
+/// Returns the joint moves that we expect to be played, given that we play mv.
+fn predict_moves<const S: usize, MODE: Mode>(board: &Bitboard<S, MODE>, mv: Move) -> [Move; S] {
+    principal_variation(board, mv, 1)[0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitboard::tests::create_duel_board;
+
+
+    #[test]
+    fn test_predict_moves() {
+        let board = create_duel_board();
+        let mvs = predict_moves(&board, Move::Right);
+        assert_eq!(mvs[0], Move::Right);
+        assert!(board.is_legal_enemy_moves(mvs));
+    }
+
+    #[test]
+    fn test_stop_ponder() {
+        let board = create_duel_board();
+        let start_time = time::Instant::now();
+        start(&board, Move::Down, time::SystemTime::now() + time::Duration::from_secs(60));
+        std::thread::sleep(time::Duration::from_millis(20));
+        stop();
+        assert!(PONDER_THREAD.lock().unwrap().is_none());
+        assert!(start_time.elapsed() < time::Duration::from_secs(10));
+    }
+}
@@ -1,43 +1,77 @@
 use super::Score;
 use super::Move;
-use std::hash::{Hash, Hasher};
-use fxhash::FxHasher64;
+use crate::config;
+use std::sync::atomic::{AtomicU8, Ordering};
 
-// const TT_LENGTH: usize = 12582917; // prime
-const TT_LENGTH: usize = 201326611; // prime
+/// The number of entries that share one bucket, a bucket fills one cache line.
+const BUCKET_SIZE: usize = 4;
 
 /// The transposition table of this battlesnake.
 /// Is encapsulated in this module and only accessible via the get and insert functions.
-static mut TABLE: Option<Vec<Entry>> = None;
+static mut TABLE: Option<Vec<Bucket>> = None;
 
-/// Initializes an empty transposition table.
+/// The current generation, entries from older generations are replaced first.
+static GENERATION: AtomicU8 = AtomicU8::new(0);
+
+#[derive(Clone, Copy, Default)]
+#[repr(align(64))]
+struct Bucket {
+    entries: [Entry; BUCKET_SIZE],
+}
+
+/// Initializes an empty transposition table, sized by the configuration.
 pub fn init() {
+    let size_mb = config::get().search.tt_size_mb;
+    init_with_size(size_mb);
+    println!("TTables initialized with {} MB", size_mb)
+}
+
+fn init_with_size(size_mb: usize) {
+    let buckets = (size_mb * 1024 * 1024 / std::mem::size_of::<Bucket>()).max(1);
     unsafe {
-        if let None = TABLE {
-            TABLE = Some(vec![Entry{data: 0, key: 0}; TT_LENGTH]);
+        if let None = *&raw const TABLE {
+            TABLE = Some(vec![Bucket::default(); buckets]);
         }
     }
-    println!("TTables initialized")
 }
 
-fn index(key: u64) -> usize {
-    (key % TT_LENGTH as u64) as usize
+/// Removes all entries from the transposition table.
+pub fn clear() {
+    unsafe {
+        if let Some(ref mut table) = *&raw mut TABLE {
+            table.fill(Bucket::default());
+        }
+    }
+    GENERATION.store(0, Ordering::Relaxed);
+}
+
+/// Starts a new generation, which marks all existing entries as stale.
+/// Should be called once per root search.
+pub fn new_generation() {
+    GENERATION.fetch_add(1, Ordering::Relaxed);
+}
+
+fn index(key: u64, len: usize) -> usize {
+    (key % len as u64) as usize
 }
 
 /// Get an entry from the transposition table
 pub fn get(key: u64) -> Option<Entry> {
     unsafe {
         if let Some(ref table) = *&raw const TABLE {
-            let entry = table[index(key)];
-            if entry.matches_key(key) {
-                return Some(entry)
-            }
+            return get_from(table, key)
         }
     }
     None
 }
 
-/// Insert an entry into the transposition table
+fn get_from(table: &[Bucket], key: u64) -> Option<Entry> {
+    table[index(key, table.len())].entries.into_iter().find(|entry| entry.matches_key(key))
+}
+
+/// Insert an entry into the transposition table.
+/// Replaces the entry with the same key, unless it is deeper and from the current generation.
+/// Otherwise replaces the entry that is the least valuable, judged by depth and age.
 pub fn insert<const S: usize>(
     key: u64,
     score: Score,
@@ -48,32 +82,44 @@ pub fn insert<const S: usize>(
 ) {
     unsafe {
         if let Some(ref mut table) = *&raw mut TABLE {
-            let index = index(key);
-            let entry = table[index];
-            if entry.matches_key(key) && entry.get_depth() > depth {
-                return
-            }
-            table[index] = Entry::new(key, score, is_lower_bound, is_upper_bound, depth, best_moves);
+            let generation = GENERATION.load(Ordering::Relaxed);
+            insert_into(table, key, Entry::new(key, score, is_lower_bound, is_upper_bound, depth, generation, best_moves));
         }
     }
 }
 
-/// The hash function that is used for the transposition table
-pub fn hash(board: &impl Hash) -> u64 {
-    #[cfg(feature = "tt")]
-    {
-        let mut hasher = FxHasher64::default();
-        board.hash(&mut hasher);
-        return hasher.finish()
+/// Inserts an entry that was created for the given key, see `insert`.
+fn insert_into(table: &mut [Bucket], key: u64, new: Entry) {
+    let (depth, generation) = (new.get_depth(), new.get_generation());
+    let len = table.len();
+    let bucket = &mut table[index(key, len)];
+    let mut replace = 0;
+    let mut replace_value = i32::MAX;
+    for (i, entry) in bucket.entries.iter().enumerate() {
+        if entry.matches_key(key) {
+            if entry.get_depth() > depth && entry.get_generation() == generation {
+                return
+            }
+            replace = i;
+            break
+        }
+        let value = if entry.data == 0 {
+            i32::MIN // empty
+        } else {
+            entry.get_depth() as i32 - 8 * generation.wrapping_sub(entry.get_generation()) as i32
+        };
+        if value < replace_value {
+            replace_value = value;
+            replace = i;
+        }
     }
-    #[cfg(not(feature = "tt"))]
-    0
+    bucket.entries[replace] = new;
 }
 
 /// A transposition table entry.
 /// Data consits of a single 64 bit integer that is completely encapsulated.
 /// Most of the available data payload is currently unused.
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Default)]
 pub struct Entry {
     key: u64,
     data: u64,
@@ -86,7 +132,8 @@ impl Entry {
     const MOVE_WIDTH: u32 = 2;
     const LOWER_BOUND_SHIFT: u32 = 40;
     const UPPER_BOUND_SHIFT: u32 = 41;
-    // const NEXT_FREE_SHIFT: u32 = 42;
+    const GENERATION_SHIFT: u32 = 42;
+    // const NEXT_FREE_SHIFT: u32 = 50;
 
     fn new<const S: usize>(
         key: u64,
@@ -94,12 +141,14 @@ impl Entry {
         is_lower_bound: bool,
         is_upper_bound: bool,
         depth: u8,
+        generation: u8,
         best_moves: [Move; S],
     ) -> Self {
         let mut data = ((score as u16) as u64) << Self::SCORE_SHIFT // rust cast semantics are annoying here
             | (depth as u64) << Self::DEPTH_SHIFT
             | (is_lower_bound as u64) << Self::LOWER_BOUND_SHIFT
-            | (is_upper_bound as u64) << Self::UPPER_BOUND_SHIFT;
+            | (is_upper_bound as u64) << Self::UPPER_BOUND_SHIFT
+            | (generation as u64) << Self::GENERATION_SHIFT;
 
         // pack moves in data
         if S <= 8 {
@@ -129,6 +178,10 @@ impl Entry {
         (self.data >> Self::DEPTH_SHIFT) as u8
     }
     
+    pub fn get_generation(&self) -> u8 {
+        (self.data >> Self::GENERATION_SHIFT) as u8
+    }
+
     pub fn get_score(&self) -> Score {
         (self.data >> Self::SCORE_SHIFT) as Score
     }
@@ -159,3 +212,41 @@ impl Entry {
         (self.data >> Self::LOWER_BOUND_SHIFT) & 0b_11 == 0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_replacement() {
+        // a local table, so that searches in other tests can't interfere
+        let mut table = vec![Bucket::default(); 64];
+        let len = table.len() as u64;
+        let keys: Vec<u64> = (1..=BUCKET_SIZE as u64 + 1).map(|i| 12345 + i * len).collect();
+        let mut generation = 0;
+
+        // fill a bucket with deep entries
+        for key in &keys[..BUCKET_SIZE] {
+            insert_into(&mut table, *key, Entry::new(*key, 10, false, false, 20, generation, [Move::Left; 2]));
+        }
+        for key in &keys[..BUCKET_SIZE] {
+            assert_eq!(get_from(&table, *key).unwrap().get_depth(), 20);
+        }
+
+        // a shallow entry of the same generation does not replace a deeper one with the same key
+        insert_into(&mut table, keys[0], Entry::new(keys[0], 5, false, false, 3, generation, [Move::Up; 2]));
+        assert_eq!(get_from(&table, keys[0]).unwrap().get_score(), 10);
+
+        // once the bucket is stale, it is replaced even by shallow entries
+        generation += 3;
+        insert_into(&mut table, keys[0], Entry::new(keys[0], 5, false, false, 3, generation, [Move::Up; 2]));
+        assert_eq!(get_from(&table, keys[0]).unwrap().get_score(), 5);
+        insert_into(&mut table, keys[BUCKET_SIZE], Entry::new(keys[BUCKET_SIZE], 7, true, false, 1, generation, [Move::Right; 2]));
+        let entry = get_from(&table, keys[BUCKET_SIZE]).unwrap();
+        assert_eq!(entry.get_score(), 7);
+        assert!(entry.is_lower_bound());
+        assert_eq!(entry.get_best_moves::<2>(), Some([Move::Right; 2]));
+        assert_eq!(entry.get_generation(), generation);
+        assert!(get_from(&table, keys[0]).is_some());
+    }
+}
@@ -0,0 +1,96 @@
+use crate::bitboard::Move;
+
+use std::cell::RefCell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use super::{STOP_SIGNAL, set_stop_signal};
+
+thread_local! {
+    /// The watch that searches on the current thread report to.
+    static WATCH: RefCell<Option<Arc<Watch>>> = const { RefCell::new(None) };
+}
+
+/// Lets another thread follow a search and stop it, so that it can always answer in time.
+#[derive(Default)]
+pub struct Watch {
+    stop: Arc<AtomicBool>,
+    /// The best move plus one, 0 means that no iteration has finished yet.
+    best_move: AtomicU8,
+}
+
+impl Watch {
+    /// Returns the best move of the last finished search iteration, if there was one.
+    pub fn best_move(&self) -> Option<Move> {
+        match self.best_move.load(Ordering::Relaxed) {
+            0 => None,
+            x => Some(Move::from_int(x - 1)),
+        }
+    }
+
+    /// Makes the watched searches return as soon as possible.
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Stops reporting to the watch when dropped, even if the search panicked.
+pub struct WatchGuard;
+
+impl Drop for WatchGuard {
+    fn drop(&mut self) {
+        WATCH.with(|x| *x.borrow_mut() = None);
+        STOP_SIGNAL.with(|x| *x.borrow_mut() = None);
+    }
+}
+
+/// Makes the searches on the current thread report to the watch and obey its stop signal,
+/// until the returned guard is dropped.
+pub fn watch(watch: Arc<Watch>) -> WatchGuard {
+    set_stop_signal(watch.stop.clone());
+    WATCH.with(|x| *x.borrow_mut() = Some(watch));
+    WatchGuard
+}
+
+/// Reports the best move of a finished search iteration on the current thread.
+pub(crate) fn report(mv: Move) {
+    WATCH.with(|x| {
+        if let Some(watch) = x.borrow().as_ref() {
+            watch.best_move.store(mv.to_int() + 1, Ordering::Relaxed);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitboard::tests::create_duel_board;
+    use crate::minimax::best_node_search;
+    use std::time;
+
+
+    #[test]
+    fn test_watch() {
+        let board = create_duel_board();
+        let watch = Arc::new(Watch::default());
+        assert_eq!(watch.best_move(), None);
+        let start_time = time::Instant::now();
+        let handle = {
+            let watch = watch.clone();
+            std::thread::spawn(move || {
+                let _guard = super::watch(watch);
+                best_node_search(&board, time::SystemTime::now() + time::Duration::from_secs(60)).best_move
+            })
+        };
+        std::thread::sleep(time::Duration::from_millis(50));
+        let best_move = watch.best_move();
+        assert!(best_move.is_some());
+        watch.stop();
+        handle.join().unwrap();
+        assert!(start_time.elapsed() < time::Duration::from_secs(10));
+
+        // reports are only taken while the guard is alive
+        report(Move::Left);
+        assert!(WATCH.with(|x| x.borrow().is_none()));
+    }
+}
@@ -0,0 +1,217 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time;
+
+use crate::bitboard::{Bitboard, Mode};
+use crate::wire_rep::GameState;
+
+/// Sessions that haven't seen a request for this long are dropped, even without an /end request.
+pub const SESSION_TIMEOUT: time::Duration = time::Duration::from_secs(10 * 60);
+
+lazy_static! {
+    static ref SESSIONS: Mutex<HashMap<String, Arc<Mutex<Session>>>> = Mutex::new(HashMap::new());
+}
+
+/// Everything we remember about a game between requests.
+pub struct Session {
+    /// The turn of the latest request in this game.
+    pub turn: u32,
+    /// Snake IDs in the order of their bitboard indices, we are always at index 0.
+    pub snake_ids: Vec<String>,
+    /// The network's share of our latency in this game, learned from the engine's latency reports.
+    network_latency: Option<time::Duration>,
+    /// How long we took to answer the latest request, measured from its receipt.
+    answer_time: Option<time::Duration>,
+    /// The bitboard of the latest move request, which is handed from one stage of the request
+    /// to the next, so that it is only built once.
+    board: Option<Box<dyn Any + Send>>,
+    last_seen: time::Instant,
+}
+
+impl Session {
+    fn new(state: &GameState) -> Self {
+        Session {
+            turn: state.turn,
+            snake_ids: vec![],
+            network_latency: None,
+            answer_time: None,
+            board: None,
+            last_seen: time::Instant::now(),
+        }
+    }
+
+    /// Resolves the snake indices and reorders the snakes in the gamestate, so that bitboards created from it use the
+    /// session's snake indices.
+    fn update(&mut self, state: &mut GameState) {
+        let mut ids = vec![state.you.id.clone()];
+        for id in self.snake_ids.iter() {
+            if *id != state.you.id && state.board.snakes.iter().any(|snake| snake.id == *id) {
+                ids.push(id.clone());
+            }
+        }
+        for snake in state.board.snakes.iter() {
+            if !ids.contains(&snake.id) {
+                ids.push(snake.id.clone());
+            }
+        }
+        state.board.snakes.sort_by_key(|snake| ids.iter().position(|id| *id == snake.id));
+
+        let consecutive = state.turn == self.turn + 1;
+        // the engine reports the latency of our answer to the previous turn
+        if let (true, Some(latency), Some(answer_time)) = (consecutive, state.you.latency, self.answer_time.take()) {
+            let sample = time::Duration::from_millis(latency.into()).saturating_sub(answer_time);
+            // follow increases immediately, but decreases only slowly
+            self.network_latency = Some(match self.network_latency {
+                Some(estimate) => sample.max((estimate * 3 + sample) / 4),
+                None => sample,
+            });
+        }
+
+        self.snake_ids = ids;
+        self.turn = state.turn;
+        self.last_seen = time::Instant::now();
+    }
+
+    /// Records how long we took to answer the latest request.
+    pub fn set_answer_time(&mut self, answer_time: time::Duration) {
+        self.answer_time = Some(answer_time);
+    }
+
+    /// Returns the estimated network round trip time of this game, if the engine reported a latency yet.
+    pub fn network_latency(&self) -> Option<time::Duration> {
+        self.network_latency
+    }
+
+    /// Takes the stored board, if it has the requested type and was built for the given turn.
+    /// Boards of earlier turns are dropped.
+    pub fn take_board<const S: usize, MODE: Mode>(&mut self, turn: u32) -> Option<Bitboard<S, MODE>> {
+        let board = self.board.take()?.downcast::<Bitboard<S, MODE>>().ok()?;
+        (board.turn as u32 == turn).then_some(*board)
+    }
+
+    pub fn set_board<const S: usize, MODE: Mode>(&mut self, board: Bitboard<S, MODE>) {
+        self.board = Some(Box::new(board));
+    }
+
+    fn is_expired(&self, now: time::Instant) -> bool {
+        now.duration_since(self.last_seen) > SESSION_TIMEOUT
+    }
+}
+
+fn prune(sessions: &mut HashMap<String, Arc<Mutex<Session>>>) {
+    let now = time::Instant::now();
+    sessions.retain(|_, session| !session.lock().unwrap().is_expired(now));
+}
+
+/// Creates a fresh session for the game, replacing any previous session with the same ID.
+pub fn start(mut state: GameState) {
+    let mut session = Session::new(&state);
+    session.update(&mut state);
+    let mut sessions = SESSIONS.lock().unwrap();
+    prune(&mut sessions);
+    sessions.insert(state.game.id.clone(), Arc::new(Mutex::new(session)));
+}
+
+/// Updates the game's session with a new move request and returns it.
+/// Also reorders the gamestate's snakes to match the session's snake indices.
+/// Creates the session if we missed the /start request.
+pub fn update(state: &mut GameState) -> Arc<Mutex<Session>> {
+    let session = {
+        let mut sessions = SESSIONS.lock().unwrap();
+        prune(&mut sessions);
+        sessions
+            .entry(state.game.id.clone())
+            .or_insert_with(|| Arc::new(Mutex::new(Session::new(state))))
+            .clone()
+    };
+    session.lock().unwrap().update(state);
+    session
+}
+
+/// Returns the number of games that currently have a session.
+pub fn active_count() -> usize {
+    SESSIONS.lock().unwrap().len()
+}
+
+/// Drops the game's session.
+pub fn end(game_id: &str) {
+    SESSIONS.lock().unwrap().remove(game_id);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitboard::mode::StandardWrapped;
+
+    fn create_state(turn: u32, heads: &[(&str, usize, usize)]) -> GameState {
+        let snakes: Vec<String> = heads.iter().map(|(id, x, y)| {
+            format!(r#"{{"id":"{}","name":"","health":100,"body":[{{"x":{},"y":{}}}],"head":{{"x":{},"y":{}}},"length":1,"shout":"","squad":""}}"#, id, x, y, x, y)
+        }).collect();
+        let val = format!(
+            r#"{{"game":{{"id":"session-test","ruleset":{{"name":"wrapped","version":"?","settings":{{}}}},"map":"standard","timeout":500,"source":""}},"turn":{},"board":{{"width":11,"height":11,"food":[],"hazards":[],"snakes":[{}]}},"you":{}}}"#,
+            turn, snakes.join(","), snakes[0],
+        );
+        serde_json::from_str(&val).unwrap()
+    }
+
+    #[test]
+    fn test_snake_indices_are_stable() {
+        let mut state = create_state(0, &[("a", 0, 0), ("b", 5, 5), ("c", 8, 8)]);
+        let mut session = Session::new(&state);
+        session.update(&mut state);
+        let mut state = create_state(1, &[("a", 0, 1), ("c", 8, 9), ("b", 5, 6)]);
+        session.update(&mut state);
+        assert_eq!(session.snake_ids, vec!["a", "b", "c"]);
+        assert_eq!(state.board.snakes.iter().map(|s| s.id.as_str()).collect::<Vec<_>>(), vec!["a", "b", "c"]);
+
+        // b died, c moves up an index
+        let mut state = create_state(2, &[("a", 0, 2), ("c", 8, 10)]);
+        session.update(&mut state);
+        assert_eq!(session.snake_ids, vec!["a", "c"]);
+    }
+
+    #[test]
+    fn test_network_latency() {
+        let mut state = create_state(0, &[("a", 0, 0)]);
+        let mut session = Session::new(&state);
+        session.update(&mut state);
+        assert_eq!(session.network_latency(), None);
+
+        // the engine's latency includes our own answer time
+        let ms = time::Duration::from_millis;
+        for (turn, latency, answer_time, expected) in [(1, 300, 200, 100), (2, 400, 200, 200), (3, 200, 200, 150)] {
+            session.set_answer_time(ms(answer_time));
+            let mut state = create_state(turn, &[("a", 0, turn as usize)]);
+            state.you.latency = Some(latency);
+            session.update(&mut state);
+            assert_eq!(session.network_latency(), Some(ms(expected)));
+        }
+
+        // after a missed turn the reported latency belongs to an answer that we didn't time
+        session.set_answer_time(ms(0));
+        let mut state = create_state(5, &[("a", 0, 5)]);
+        state.you.latency = Some(1000);
+        session.update(&mut state);
+        assert_eq!(session.network_latency(), Some(ms(150)));
+    }
+
+    #[test]
+    fn test_session_board() {
+        let mut state = create_state(3, &[("a", 0, 0), ("b", 5, 5)]);
+        let mut session = Session::new(&state);
+        session.update(&mut state);
+        assert!(session.take_board::<2, StandardWrapped>(3).is_none());
+        let board = Bitboard::<2, StandardWrapped>::from_gamestate(state);
+        session.set_board(board.clone());
+        assert!(session.take_board::<3, StandardWrapped>(3).is_none());
+        session.set_board(board.clone());
+        assert!(session.take_board::<2, StandardWrapped>(4).is_none());
+        session.set_board(board.clone());
+        assert_eq!(session.take_board::<2, StandardWrapped>(3), Some(board));
+        assert!(session.take_board::<2, StandardWrapped>(3).is_none());
+
+        assert!(!session.is_expired(time::Instant::now()));
+        assert!(session.is_expired(time::Instant::now() + SESSION_TIMEOUT * 2));
+    }
+}
@@ -1,12 +1,21 @@
 use crate::bitboard::mode::Mode;
 use crate::bitboard::*;
 use crate::bitboard::move_gen::*;
+use crate::minimax;
+use crate::config::{self, SearchConfig};
+use crate::engine::{self, Engine, Evaluation, SearchResult};
 
 use arrayvec::ArrayVec;
 use rand::Rng;
 use rand_pcg::Pcg64Mcg;
 use std::time;
 
+/// The number of turns that a solo playout has to survive to be counted as a win.
+const SOLO_PLAYOUT_TURNS: u16 = 50;
+
+/// The number of iterations between reports of the best move so far.
+const REPORT_EVERY: u64 = 1024;
+
 enum Moves<const S: usize> {
     Me(ArrayVec<Move, 4>),
     Enemies(Vec<[Move; S]>),
@@ -81,7 +90,7 @@ fn expand<const S: usize, MODE: Mode>(tree: &mut Vec<Node<S, MODE>>, node_idx: u
         } else {
             panic!("Min node does not have parent");
         };
-        (board.apply_moves.clone())(&mut board, &moves);
+        board.play_moves(&moves);
     }
     let idx = tree.len();
     let new = Node::<S, MODE>::new(board, idx, moves_idx, Some(tree[node_idx].idx), !tree[node_idx].max);
@@ -90,23 +99,94 @@ fn expand<const S: usize, MODE: Mode>(tree: &mut Vec<Node<S, MODE>>, node_idx: u
     idx
 }
 
-pub fn search<const S: usize, MODE: Mode>(board: &Bitboard<S, MODE>, deadline: time::SystemTime) -> (Move, f64) {
+/// Monte Carlo tree search with UCB1, see `search`.
+pub struct Mcts;
+
+impl Engine for Mcts {
+    fn search<const S: usize, MODE: Mode>(&self, board: &Bitboard<S, MODE>, deadline: time::SystemTime) -> SearchResult<S> {
+        search(board, deadline)
+    }
+}
+
+pub fn search<const S: usize, MODE: Mode>(board: &Bitboard<S, MODE>, deadline: time::SystemTime) -> SearchResult<S> {
     let mut tree = Vec::<Node<S, MODE>>::with_capacity(100000);
     let mut rng = Pcg64Mcg::new(91825765198273048172569872943871926276_u128);
     let mut node_counter = 0;
     let mut iteration_counter = 0;
+    let mut max_tree_depth = 0;
     let start_time = time::Instant::now();
 
     // create root
     tree.push(Node::<S, MODE>::new(board.clone(), 0, 0, None, true));
 
     // compute
-    while time::SystemTime::now() < deadline {
+    let config = &config::get().search;
+    while time::SystemTime::now() < deadline && !minimax::is_stopped() {
         iteration_counter += 1;
-        once(&mut tree, &mut rng, &mut node_counter);
+        max_tree_depth = max_tree_depth.max(once(&mut tree, &mut rng, &mut node_counter, config));
+        if iteration_counter % REPORT_EVERY == 0 {
+            minimax::watch::report(best_move(&tree).0);
+        }
+    }
+
+    let (best_move, best_winrate) = best_move(&tree);
+    let nodes_per_second = engine::nodes_per_second(node_counter, start_time.elapsed());
+    println!("{:?} iterations, {:?} nodes total, {:?} nodes per second", iteration_counter, node_counter, nodes_per_second);
+    println!("{:?} with wr {}\n", best_move, best_winrate);
+    SearchResult{
+        best_move,
+        evaluation: Evaluation::Winrate(best_winrate),
+        depth: 0,
+        // a joint move takes a max and a min node in the tree
+        seldepth: (max_tree_depth / 2).min(u8::MAX as usize) as u8,
+        nodes: node_counter,
+        nodes_per_second,
+        tt_hit_rate: 0.0,
+        pv: principal_variation(&tree, best_move),
+        root_moves: root_moves(&tree),
+    }
+}
+
+/// Returns the winrate of every root move that has been visited.
+fn root_moves<const S: usize, MODE: Mode>(tree: &[Node<S, MODE>]) -> Vec<(Move, Evaluation)> {
+    tree[0].children.iter().enumerate()
+        .filter_map(|(i, child)| child.map(|idx| {
+            (tree[0].moves.get_my_move(i), Evaluation::Winrate(tree[idx].wins as f64 / tree[idx].visits as f64))
+        }))
+        .collect()
+}
+
+/// Follows the most visited children after we play mv at the root, as long as both our move and the enemies' moves are in the tree.
+fn principal_variation<const S: usize, MODE: Mode>(tree: &[Node<S, MODE>], mv: Move) -> Vec<[Move; S]> {
+    let most_visited = |node: &Node<S, MODE>| {
+        node.children.iter().enumerate()
+            .filter_map(|(i, child)| child.map(|idx| (i, idx)))
+            .max_by_key(|(_, idx)| tree[*idx].visits)
+    };
+    let mut pv = vec![];
+    let mut node_idx = 0;
+    let mut next = match &tree[0].moves {
+        Moves::Me(mvs) => mvs.iter().position(|x| *x == mv).and_then(|i| tree[0].children[i].map(|idx| (i, idx))),
+        Moves::Enemies(_) => panic!("search root does not have me moves"),
+    };
+    while let Some((i, min_idx)) = next {
+        let Some((j, max_idx)) = most_visited(&tree[min_idx]) else {
+            break
+        };
+        let mut mvs = match &tree[min_idx].moves {
+            Moves::Enemies(mvs) => mvs[j],
+            Moves::Me(_) => panic!("Min node does not have enemies moves"),
+        };
+        mvs[0] = tree[node_idx].moves.get_my_move(i);
+        pv.push(mvs);
+        node_idx = max_idx;
+        next = most_visited(&tree[node_idx]);
     }
+    pv
+}
 
-    // extract the result from the tree
+/// Extracts the root move with the best winrate from the tree.
+fn best_move<const S: usize, MODE: Mode>(tree: &[Node<S, MODE>]) -> (Move, f64) {
     let moves = if let Moves::Me(mvs) = &tree[0].moves {
         mvs.clone()
     } else {
@@ -124,22 +204,23 @@ pub fn search<const S: usize, MODE: Mode>(board: &Bitboard<S, MODE>, deadline: t
             }
         }
     }
-    println!("{:?} iterations, {:?} nodes total, {:?} nodes per second", iteration_counter, node_counter, node_counter as u128 * (time::Duration::from_secs(1).as_nanos() / start_time.elapsed().as_nanos()));
-    println!("{:?} with wr {}\n", best_move, best_winrate);
     (best_move, best_winrate)
 }
 
-fn once<const S: usize, MODE: Mode>(tree: &mut Vec<Node<S, MODE>>, rng: &mut impl Rng, node_counter: &mut u64) {
+/// Runs one iteration and returns the depth of the node that it added to the tree.
+fn once<const S: usize, MODE: Mode>(tree: &mut Vec<Node<S, MODE>>, rng: &mut impl Rng, node_counter: &mut u64, config: &SearchConfig) -> usize {
     // select
     let mut node_idx = 0;
-    let mut moves_idx = select_child(tree, node_idx);
+    let mut tree_depth = 1;
+    let mut moves_idx = select_child(tree, node_idx, config.ucb_c);
     loop {
         if let Some(idx) = tree[node_idx].children[moves_idx] {
             node_idx = idx;
+            tree_depth += 1;
         } else {
             break
         };
-        moves_idx = select_child(tree, node_idx);
+        moves_idx = select_child(tree, node_idx, config.ucb_c);
     }
 
     node_idx = expand(tree, node_idx, moves_idx);
@@ -147,10 +228,11 @@ fn once<const S: usize, MODE: Mode>(tree: &mut Vec<Node<S, MODE>>, rng: &mut imp
         value
     } else {
         // simulate
-        moves_idx = select_child(tree, node_idx);
-        playout(tree, node_idx, moves_idx, rng, node_counter)
+        moves_idx = select_child(tree, node_idx, config.ucb_c);
+        playout(tree, node_idx, moves_idx, rng, node_counter, config)
     };
     propagate(tree, node_idx, result);
+    tree_depth
 }
 
 fn propagate<const S: usize, MODE: Mode>(tree: &mut Vec<Node<S, MODE>>, mut node_idx: usize, result: i8) {
@@ -169,7 +251,7 @@ fn propagate<const S: usize, MODE: Mode>(tree: &mut Vec<Node<S, MODE>>, mut node
 }
 
 // returns the winner's snake index
-fn playout<const S: usize, MODE: Mode>(tree: &mut Vec<Node<S, MODE>>, node_idx: usize, moves_idx: usize, rng: &mut impl Rng, node_counter: &mut u64) -> i8 {
+fn playout<const S: usize, MODE: Mode>(tree: &mut Vec<Node<S, MODE>>, node_idx: usize, moves_idx: usize, rng: &mut impl Rng, node_counter: &mut u64, config: &SearchConfig) -> i8 {
     let mut board = tree[node_idx].board.clone();
     let mut moves = match &tree[node_idx].moves {
         Moves::Me(mvs) => {
@@ -187,23 +269,25 @@ fn playout<const S: usize, MODE: Mode>(tree: &mut Vec<Node<S, MODE>>, node_idx:
             tmp
         },
     };
+    let mut turns = 0;
     while !board.is_terminal() {
+        // solo games only end with our death, surviving long enough counts as a win
+        if board.gamemode == Gamemode::Solo && turns == SOLO_PLAYOUT_TURNS {
+            return 1
+        }
+        turns += 1;
         *node_counter += 1;
-        (board.apply_moves.clone())(&mut board, &moves);
-        moves = random_move_combination(&board, rng);
-    }
-    if board.snakes[0].is_alive() {
-        return 1
-    }
-    for snake in board.snakes[1..].iter() {
-        if snake.is_alive() {
-            return -1
+        board.play_moves(&moves);
+        // spawns are only sampled in playouts, the tree itself stays deterministic
+        if config.sample_food_spawns {
+            board.spawn_food(rng);
         }
+        moves = random_move_combination(&board, rng);
     }
-    return 0
+    board.win_draw_loss().unwrap_or(0)
 }
 
-fn select_child<const S: usize, MODE: Mode>(tree: &Vec<Node<S, MODE>>, node: usize) -> usize {
+fn select_child<const S: usize, MODE: Mode>(tree: &Vec<Node<S, MODE>>, node: usize, ucb_c: f64) -> usize {
     let parent_visits = tree[node].visits;
     let parent_max = tree[node].max;
 
@@ -212,7 +296,7 @@ fn select_child<const S: usize, MODE: Mode>(tree: &Vec<Node<S, MODE>>, node: usi
     for (i, x) in tree[node].children.iter().enumerate() {
         if let Some(child_idx) = x {
             let child = &tree[*child_idx];
-            let val = ucb1(parent_visits.into(), child.visits.into(), child.wins.into());
+            let val = ucb1(parent_visits.into(), child.visits.into(), child.wins.into(), ucb_c);
             if best_val < val {
                 best_val = val;
                 best_moves_idx = i;
@@ -224,8 +308,7 @@ fn select_child<const S: usize, MODE: Mode>(tree: &Vec<Node<S, MODE>>, node: usi
     best_moves_idx
 }
 
-fn ucb1(parent_visits: f64, child_visits: f64, child_wins: f64) -> f64 {
-    const C: f64 = 1.5;
+fn ucb1(parent_visits: f64, child_visits: f64, child_wins: f64, ucb_c: f64) -> f64 {
     let winrate = child_wins / child_visits;
-    winrate + C * (parent_visits.ln() / child_visits).sqrt()
+    winrate + ucb_c * (parent_visits.ln() / child_visits).sqrt()
 }
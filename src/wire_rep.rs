@@ -1,18 +1,126 @@
 use std::collections::HashMap;
 
-use serde::{Deserialize, Serialize};
-use serde_json::{Value, Map};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde::de::Error;
+use serde_json::Value;
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Clone, Deserialize, Serialize, Debug)]
 pub struct Game {
     pub id: String,
-    pub ruleset: Map<String, Value>,
+    pub ruleset: Ruleset,
     pub map: String,
     pub timeout: u32,
     pub source: String,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Clone, Deserialize, Serialize, Debug, Default, PartialEq, Eq)]
+pub struct Ruleset {
+    pub name: RulesetName,
+    #[serde(default)]
+    pub version: String,
+    #[serde(default)]
+    pub settings: RulesetSettings,
+}
+
+/// The rulesets that we know how to play.
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum RulesetName {
+    #[default]
+    Standard,
+    Solo,
+    Royale,
+    Squad,
+    Constrictor,
+    Wrapped,
+    WrappedConstrictor,
+    /// Any other name, which is played by the standard rules.
+    #[serde(other)]
+    Unknown,
+}
+
+impl RulesetName {
+    /// Returns true if the ruleset is played on a board with wrapped edges.
+    pub fn is_wrapped(&self) -> bool {
+        matches!(self, Self::Wrapped | Self::WrappedConstrictor)
+    }
+}
+
+/// Missing settings take the game's default values.
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RulesetSettings {
+    #[serde(deserialize_with = "percentage")]
+    pub food_spawn_chance: u8,
+    pub minimum_food: u8,
+    pub hazard_damage_per_turn: i8,
+    pub royale: RoyaleSettings,
+    pub squad: SquadSettings,
+}
+
+impl Default for RulesetSettings {
+    fn default() -> Self {
+        RulesetSettings{
+            food_spawn_chance: 15,
+            minimum_food: 1,
+            hazard_damage_per_turn: 14,
+            royale: RoyaleSettings::default(),
+            squad: SquadSettings::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RoyaleSettings {
+    /// Used by the royale, sinkholes and healing pools maps, 0 means never.
+    pub shrink_every_n_turns: u16,
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SquadSettings {
+    pub allow_body_collisions: bool,
+    pub shared_elimination: bool,
+    pub shared_health: bool,
+    pub shared_length: bool,
+}
+
+impl Default for SquadSettings {
+    fn default() -> Self {
+        SquadSettings{
+            allow_body_collisions: true,
+            shared_elimination: true,
+            shared_health: true,
+            shared_length: true,
+        }
+    }
+}
+
+fn percentage<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u8, D::Error> {
+    let x = u8::deserialize(deserializer)?;
+    if x > 100 {
+        return Err(D::Error::custom(format!("invalid percentage {}, expected at most 100", x)))
+    }
+    Ok(x)
+}
+
+/// The engine sends latencies as strings, which are empty before the first move.
+fn latency<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u32>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Latency {
+        Number(u32),
+        Text(String),
+    }
+    match Latency::deserialize(deserializer)? {
+        Latency::Number(x) => Ok(Some(x)),
+        Latency::Text(x) if x.is_empty() => Ok(None),
+        Latency::Text(x) => x.parse().map(Some).map_err(|_| D::Error::custom(format!("invalid latency {:?}", x))),
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
 pub struct Board {
     pub height: usize,
     pub width: usize,
@@ -25,10 +133,14 @@ pub struct Board {
 pub struct Battlesnake {
     pub id: String,
     pub name: String,
-    pub health: i8,
+    /// Wider than the bitboard's health, so that out of range values can be reported by validation.
+    pub health: i32,
     pub body: Vec<Coord>,
     pub head: Coord,
     pub length: usize,
+    /// How long the snake took to answer the previous turn in milliseconds, as measured by the game engine.
+    #[serde(default, deserialize_with = "latency", skip_serializing_if = "Option::is_none")]
+    pub latency: Option<u32>,
 
     // Used in non-standard game modes
     pub shout: Option<String>,
@@ -38,16 +150,158 @@ pub struct Battlesnake {
     pub next_move: Option<String>,
 }
 
+/// Coordinates are signed, so that requests with negative ones can be rejected by validation.
 #[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
 pub struct Coord {
-    pub x: usize,
-    pub y: usize,
+    pub x: i32,
+    pub y: i32,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Clone, Deserialize, Serialize, Debug)]
 pub struct GameState {
     pub game: Game,
     pub turn: u32,
     pub board: Board,
     pub you: Battlesnake,
 }
+
+/// A problem with a request that would keep us from building a board out of it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ValidationError {
+    EmptyBoard{width: usize, height: usize},
+    OutOfBounds{field: String, x: i32, y: i32},
+    EmptyBody{snake: String},
+    HeadNotOnBody{snake: String},
+    DetachedBody{snake: String, segment: usize},
+    InvalidHealth{snake: String, health: i32},
+    LengthMismatch{snake: String, length: usize, body: usize},
+    TooLong{snake: String, length: usize},
+    UnknownYou{id: String},
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::EmptyBoard{width, height} => write!(f, "board of size {}x{} has no cells", width, height),
+            Self::OutOfBounds{field, x, y} => write!(f, "{} at ({}, {}) lies outside the board", field, x, y),
+            Self::EmptyBody{snake} => write!(f, "snake {} has an empty body", snake),
+            Self::HeadNotOnBody{snake} => write!(f, "head of snake {} is not the first segment of its body", snake),
+            Self::DetachedBody{snake, segment} => write!(f, "body segment {} of snake {} is not next to the previous one", segment, snake),
+            Self::InvalidHealth{snake, health} => write!(f, "health {} of snake {} is not between 0 and 100", health, snake),
+            Self::LengthMismatch{snake, length, body} => write!(f, "length {} of snake {} does not match its {} body segments", length, snake, body),
+            Self::TooLong{snake, length} => write!(f, "length {} of snake {} is more than {}", length, snake, u8::MAX),
+            Self::UnknownYou{id} => write!(f, "you ({}) is not a snake on the board", id),
+        }
+    }
+}
+
+impl GameState {
+    /// Picks the board size, the ruleset, the snakes' bodies and our head out of a request that
+    /// doesn't parse as a whole, skipping whatever is broken. Enough for `move_gen::fallback_move`.
+    pub fn from_partial_json(value: &Value) -> Self {
+        let coord = |value: &Value| Coord::deserialize(value).ok();
+        let snake = |value: &Value| Battlesnake{
+            id: value["id"].as_str().unwrap_or_default().to_string(),
+            name: String::new(),
+            health: 0,
+            body: value["body"].as_array().map(|body| body.iter().filter_map(coord).collect()).unwrap_or_default(),
+            head: coord(&value["head"]).unwrap_or(Coord{x: -1, y: -1}),
+            length: 0,
+            latency: None,
+            shout: None,
+            squad: None,
+            next_move: None,
+        };
+        GameState{
+            game: Game{
+                id: String::new(),
+                ruleset: Ruleset{
+                    name: RulesetName::deserialize(&value["game"]["ruleset"]["name"]).unwrap_or_default(),
+                    ..Default::default()
+                },
+                map: String::new(),
+                timeout: 0,
+                source: String::new(),
+            },
+            turn: 0,
+            board: Board{
+                height: value["board"]["height"].as_u64().unwrap_or(0) as usize,
+                width: value["board"]["width"].as_u64().unwrap_or(0) as usize,
+                food: vec![],
+                snakes: value["board"]["snakes"].as_array().map(|snakes| snakes.iter().map(snake).collect()).unwrap_or_default(),
+                hazards: vec![],
+            },
+            you: snake(&value["you"]),
+        }
+    }
+
+    /// Checks everything that `Bitboard::from_gamestate` relies on and reports every problem found.
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let (w, h) = (self.board.width as i32, self.board.height as i32);
+        if w <= 0 || h <= 0 {
+            return Err(vec![ValidationError::EmptyBoard{width: self.board.width, height: self.board.height}])
+        }
+        let wrapped = self.game.ruleset.name.is_wrapped();
+        let mut errors = vec![];
+        let mut check_bounds = |field: String, coord: &Coord| {
+            if coord.x < 0 || coord.y < 0 || coord.x >= w || coord.y >= h {
+                errors.push(ValidationError::OutOfBounds{field, x: coord.x, y: coord.y});
+                false
+            } else {
+                true
+            }
+        };
+        for (i, food) in self.board.food.iter().enumerate() {
+            check_bounds(format!("food[{}]", i), food);
+        }
+        for (i, hazard) in self.board.hazards.iter().enumerate() {
+            check_bounds(format!("hazards[{}]", i), hazard);
+        }
+        let mut detached = vec![];
+        let mut invalid = vec![];
+        for snake in self.board.snakes.iter() {
+            if !(0..=100).contains(&snake.health) {
+                invalid.push(ValidationError::InvalidHealth{snake: snake.id.clone(), health: snake.health});
+            }
+            if !snake.body.is_empty() && snake.length != snake.body.len() {
+                invalid.push(ValidationError::LengthMismatch{snake: snake.id.clone(), length: snake.length, body: snake.body.len()});
+            } else if snake.length > u8::MAX as usize {
+                invalid.push(ValidationError::TooLong{snake: snake.id.clone(), length: snake.length});
+            }
+            let mut in_bounds = check_bounds(format!("head of snake {}", snake.id), &snake.head);
+            for (i, segment) in snake.body.iter().enumerate() {
+                in_bounds &= check_bounds(format!("body[{}] of snake {}", i, snake.id), segment);
+            }
+            if snake.body.is_empty() {
+                detached.push(ValidationError::EmptyBody{snake: snake.id.clone()});
+            } else if snake.body[0] != snake.head {
+                detached.push(ValidationError::HeadNotOnBody{snake: snake.id.clone()});
+            } else if in_bounds && let Some(segment) = (1..snake.body.len()).find(|&i| !snake.body[i-1].touches(&snake.body[i], w, h, wrapped)) {
+                detached.push(ValidationError::DetachedBody{snake: snake.id.clone(), segment});
+            }
+        }
+        errors.append(&mut detached);
+        errors.append(&mut invalid);
+        // the bitboard recognizes us by our head
+        if !self.board.snakes.iter().any(|snake| snake.id == self.you.id && snake.head == self.you.head) {
+            errors.push(ValidationError::UnknownYou{id: self.you.id.clone()});
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+impl Coord {
+    /// Returns true if the other coordinate is the same cell or one step away.
+    fn touches(&self, other: &Coord, w: i32, h: i32, wrapped: bool) -> bool {
+        let (mut dx, mut dy) = ((self.x - other.x).abs(), (self.y - other.y).abs());
+        if wrapped {
+            dx = dx.min(w - dx);
+            dy = dy.min(h - dy);
+        }
+        dx + dy <= 1
+    }
+}